@@ -0,0 +1,170 @@
+//! Checks of the archive files reported by `--dry-run` and `--verify`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use comlib::validate::DEFAULT_TOLERANCE;
+use comlib::{Finding, Seconds, SummariesAll};
+
+/// Something wrong with the days read, the import itself still goes ahead.
+#[derive(Debug, PartialEq)]
+pub enum Issue {
+    /// The day is in several files, the last one in path order is imported
+    Duplicate {
+        date: NaiveDate,
+        files: Vec<PathBuf>,
+    },
+    /// Nothing was recorded that day
+    Empty { date: NaiveDate, file: PathBuf },
+    /// Found by [`comlib::validate`]
+    Invalid { file: PathBuf, finding: Finding },
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Issue::Duplicate { date, files } => {
+                let files: Vec<String> = files.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "duplicate {} in {}", date, files.join(", "))
+            }
+            Issue::Empty { date, file } => write!(f, "empty {} in {}", date, file.display()),
+            Issue::Invalid { file, finding } => {
+                write!(f, "invalid {} : {}", file.display(), finding)
+            }
+        }
+    }
+}
+
+/// Empty days of `file` and what [`comlib::validate`] finds in it.
+pub fn file_issues(all: &SummariesAll, file: &Path) -> Vec<Issue> {
+    let empty = all
+        .summaries
+        .data
+        .iter()
+        .filter(|day| day.grand_total.total_seconds == Seconds(0.0))
+        .filter_map(|day| day.range.parsed_date().ok())
+        .map(|date| Issue::Empty {
+            date,
+            file: file.to_path_buf(),
+        });
+    let invalid = comlib::validate(all, DEFAULT_TOLERANCE)
+        .into_iter()
+        .map(|finding| Issue::Invalid {
+            file: file.to_path_buf(),
+            finding,
+        });
+    empty.chain(invalid).collect()
+}
+
+/// Days read from more than one file.
+pub fn duplicates(days: &[(NaiveDate, PathBuf)]) -> Vec<Issue> {
+    let mut files: BTreeMap<NaiveDate, Vec<PathBuf>> = BTreeMap::new();
+    for (date, file) in days {
+        files.entry(*date).or_default().push(file.clone());
+    }
+    files
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(date, files)| Issue::Duplicate { date, files })
+        .collect()
+}
+
+/// Days that would be inserted and updated, printed as a table by month.
+pub struct ImportPlan {
+    months: BTreeMap<String, (usize, usize)>,
+}
+
+impl ImportPlan {
+    /// `dates` read from the files against the `stored` ones.
+    pub fn new(dates: &BTreeSet<NaiveDate>, stored: &BTreeSet<NaiveDate>) -> ImportPlan {
+        let mut months: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for date in dates {
+            let counts = months.entry(date.format("%Y-%m").to_string()).or_default();
+            if stored.contains(date) {
+                counts.1 += 1;
+            } else {
+                counts.0 += 1;
+            }
+        }
+        ImportPlan { months }
+    }
+}
+
+impl Display for ImportPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        writeln!(f, "{:<8} {:>7} {:>7}", "month", "insert", "update")?;
+        let mut total = (0, 0);
+        for (month, (insert, update)) in &self.months {
+            writeln!(f, "{:<8} {:>7} {:>7}", month, insert, update)?;
+            total.0 += insert;
+            total.1 += update;
+        }
+        writeln!(f, "{:<8} {:>7} {:>7}", "total", total.0, total.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comlib::fixtures;
+    use serde_json::json;
+
+    fn summaries(days: &[(&str, f64, &[f64])]) -> SummariesAll {
+        let days = days
+            .iter()
+            .map(|(date, grand_total, editors)| {
+                let mut day = fixtures::day(date, *grand_total);
+                day["editors"] = json!(editors
+                    .iter()
+                    .map(|secs| fixtures::detail("x", *secs))
+                    .collect::<Vec<_>>());
+                day
+            })
+            .collect();
+        serde_json::from_value(fixtures::summaries_all(days)).unwrap()
+    }
+
+    #[test]
+    fn reports_empty_days_and_findings() {
+        let file = Path::new("res_20210222-20210224.json");
+        let all = summaries(&[
+            ("2021-02-22", 0.0, &[]),
+            ("2021-02-23", 3600.0, &[1800.0, 1800.4]),
+            ("2021-02-24", 3600.0, &[1800.0]),
+        ]);
+        let issues: Vec<String> = file_issues(&all, file)
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                "empty 2021-02-22 in res_20210222-20210224.json",
+                "invalid res_20210222-20210224.json : 2021-02-24: editors add up to 1800s, grand_total is 3600s",
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_inserts_and_updates_by_month() {
+        let d = |m, d| NaiveDate::from_ymd_opt(2021, m, d).unwrap();
+        let dates = vec![d(1, 30), d(1, 31), d(2, 1)].into_iter().collect();
+        let stored = vec![d(1, 31), d(3, 1)].into_iter().collect();
+        let table = ImportPlan::new(&dates, &stored).to_string();
+        let lines: Vec<Vec<&str>> = table
+            .lines()
+            .map(|l| l.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                vec!["month", "insert", "update"],
+                vec!["2021-01", "1", "1"],
+                vec!["2021-02", "1", "0"],
+                vec!["total", "2", "1"],
+            ]
+        );
+    }
+}
@@ -1,12 +1,22 @@
-use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::NaiveDate;
+use clap::Parser;
+use comdb::SummaryRow;
+use comlib::validate::Report;
+use comlib::SummariesAll;
 use config::Config;
-use futures::executor::block_on;
+use glob::{MatchOptions, Pattern};
+use indicatif::{ProgressBar, ProgressStyle};
 use once_cell::sync::Lazy;
-use rust_decimal::{Decimal, prelude::FromPrimitive};
-use serde::__private::de;
-use tokio_postgres::{Client, Error as PgError, NoTls};
-use serde_json::{json};
-use rust_decimal_macros::dec;
+use rayon::prelude::*;
+use walkdir::WalkDir;
+
+mod check;
+
+use check::{ImportPlan, Issue};
 
 static SETTINGS: Lazy<Config> = Lazy::new(|| {
     let mut settings = Config::default();
@@ -16,140 +26,310 @@ static SETTINGS: Lazy<Config> = Lazy::new(|| {
     settings
 });
 
-async fn update_log(
-    client: &Client,
-    log_dt: &chrono::NaiveDate,
-    editors: &serde_json::Value,
-    langs: &serde_json::Value,
-    machines: &serde_json::Value,
-    projects: &serde_json::Value,
-    depends: &serde_json::Value,
-    grand_total_sec: &Decimal,
-) -> Result<u64, PgError> {
-    let stmt = client
-        .prepare(r#"
-UPDATE wakatime_summary SET
-    editors=$2,
-    langs=$3,
-    machine=$4,
-    projects=$5,
-    depends=$6,
-    grand_total_sec=$7
-WHERE date=$1"#)
-        .await
-        .unwrap();
-    client.execute(&stmt, &[
-        &log_dt,
-        &editors,
-        &langs,
-        &machines,
-        &projects,
-        &depends,
-        &grand_total_sec
-    ]).await
+#[derive(Debug, Parser)]
+#[clap(
+    name = "bulk_regist",
+    about = "Registers a tree of WakaTime JSON files to the database"
+)]
+struct Cli {
+    /// Directory searched recursively for the JSON files
+    #[clap(default_value = ".")]
+    dir: PathBuf,
+    /// Glob of the files to read, relative to `dir`
+    #[clap(long, default_value = "**/*.json")]
+    include: Vec<String>,
+    /// Glob of the files to leave out, relative to `dir`
+    #[clap(long)]
+    exclude: Vec<String>,
+    /// Check the files and show what would be inserted and updated without
+    /// writing anything
+    #[clap(long)]
+    dry_run: bool,
+    /// Only check the files, without the database, and fail when a file
+    /// cannot be read or does not add up
+    #[clap(long, conflicts_with = "dry-run")]
+    verify: bool,
+    /// Load everything in one transaction through COPY, for large archives
+    #[clap(long)]
+    copy: bool,
+    /// Days written per transaction, or per COPY with `--copy`
+    #[clap(long, default_value_t = comdb::DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let db_url = SETTINGS.get_str("db_url")?;
-    let (client, connection) = tokio_postgres::connect(&db_url, NoTls).await?;
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            println!("connection error: {}", e);
-        }
-    });
+/// Include and exclude globs matched against the path below the scanned
+/// directory, `*` does not cross a `/`.
+struct FileFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl FileFilter {
+    fn new(include: &[String], exclude: &[String]) -> Result<FileFilter, glob::PatternError> {
+        let compile = |globs: &[String]| -> Result<Vec<Pattern>, _> {
+            globs.iter().map(|g| Pattern::new(g)).collect()
+        };
+        Ok(FileFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
 
-    let cdir = std::env::current_dir()?;
-    let read_dir = std::fs::read_dir(cdir.as_os_str())?;
-    for fnm in read_dir.into_iter() {
-        if fnm.is_err() {
+    fn matches(&self, relative: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let matched = |patterns: &[Pattern]| {
+            patterns
+                .iter()
+                .any(|p| p.matches_path_with(relative, options))
+        };
+        matched(&self.include) && !matched(&self.exclude)
+    }
+}
+
+/// Files below `dir` passing `filter`, sorted by path.
+fn archive_files(dir: &Path, filter: &FileFilter) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
             continue;
         }
-        let fentry = fnm.unwrap();
-        let fnm = fentry.file_name();
-        let fnm = fnm.to_string_lossy();
-        let fnm_bara: Vec<&str> = fnm.split('.').collect();
-        let ext = fnm_bara.last().unwrap();
-        if *ext != "json" {
-            continue;
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        if filter.matches(relative) {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+/// Days of one file, a range file gives one row per day of `range.date`.
+struct ArchiveFile {
+    rows: Vec<SummaryRow>,
+    issues: Vec<Issue>,
+}
+
+fn read_file(path: &Path) -> anyhow::Result<ArchiveFile> {
+    let json = std::fs::read_to_string(path)?;
+    let all: SummariesAll = serde_json::from_str(&json)?;
+    let issues = check::file_issues(&all, path);
+    let rows = SummaryRow::from_summaries(&all)?;
+    Ok(ArchiveFile { rows, issues })
+}
+
+/// Prints the issues found, failing on unreadable files and on findings of
+/// [`comlib::validate`].
+fn verify(
+    days: &[(NaiveDate, PathBuf)],
+    mut issues: Vec<Issue>,
+    skipped: usize,
+) -> anyhow::Result<()> {
+    issues.extend(check::duplicates(days));
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    let report = Report {
+        days: days.len(),
+        skipped,
+        findings: issues
+            .iter()
+            .filter(|issue| matches!(issue, Issue::Invalid { .. }))
+            .count(),
+    };
+    println!("{}", report);
+    if !report.passed() {
+        return Err(anyhow::anyhow!("verification failed"));
+    }
+    Ok(())
+}
+
+/// Prints the issues found and the days that would be written.
+async fn dry_run(
+    db_url: &str,
+    days: &[(NaiveDate, PathBuf)],
+    mut issues: Vec<Issue>,
+    skipped: usize,
+) -> anyhow::Result<()> {
+    issues.extend(check::duplicates(days));
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    let dates: BTreeSet<NaiveDate> = days.iter().map(|(date, _)| *date).collect();
+    // a SQLite file that does not exist yet is all inserts
+    let stored = match (comdb::open(db_url).await?, dates.iter().next()) {
+        (Some(db), Some(first)) if db.schema_version().await.is_some() => {
+            db.stored_dates(*first).await?
         }
-        let log_dt = fnm_bara
-            .first()
-            .unwrap_or(&"")
-            .split('_')
-            .last()
-            .unwrap_or(&"");
-        println!("{:?}", &fnm);
-
-        let log_dt = chrono::NaiveDate::parse_from_str(&log_dt, "%Y%m%d")?;
-
-        let json = std::fs::read_to_string(fentry.path().to_str().unwrap_or(""))?;
-        let jval: serde_json::Value = serde_json::from_str(&json).unwrap();
-        let null_obj = json!({});
-        let null_arr = json!([{}]);
-        let summary_data = jval.get("summaries")
-            .unwrap_or(&null_obj)
-            .get("data")
-            .unwrap_or(&null_arr)
-            .get(0)
-            .unwrap_or(&null_obj);
-        let depends = summary_data
-            .get("dependencies")
-            .unwrap_or(&null_arr);
-        let editors = summary_data
-            .get("editors")
-            .unwrap_or(&null_arr);
-        let langs = summary_data
-            .get("languages")
-            .unwrap_or(&null_arr);
-        let machines = summary_data
-            .get("machines")
-            .unwrap_or(&null_arr);
-        let projects = summary_data
-            .get("projects")
-            .unwrap_or(&null_arr);
-        
-        let grand_total_sec = match summary_data.get("grand_total") {
-            Some(j) => {
-                match j.get("total_seconds") {
-                    Some(v) => v.as_f64().unwrap_or(0.0),
-                    None => 0.0,
-                }
+        _ => BTreeSet::new(),
+    };
+    print!("{}", ImportPlan::new(&dates, &stored));
+    println!(
+        "{} file(s) skipped, {} issue(s), nothing written",
+        skipped,
+        issues.len()
+    );
+    // as the import itself would
+    if skipped > 0 {
+        return Err(anyhow::anyhow!(
+            "{} file(s) skipped, 0 day(s) failed",
+            skipped
+        ));
+    }
+    Ok(())
+}
+
+/// Loads `rows` with [`comdb::Database::copy_rows`] behind a progress bar,
+/// which turns into a spinner while the copied days are merged.
+async fn copy_rows(
+    db: &mut comdb::Database,
+    rows: &[SummaryRow],
+    batch_size: usize,
+) -> anyhow::Result<comdb::UpsertReport> {
+    // copy_rows writes each date once
+    let days = rows
+        .iter()
+        .map(|row| row.date)
+        .collect::<BTreeSet<_>>()
+        .len() as u64;
+    let bar = ProgressBar::new(days);
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{elapsed_precise} [{bar:40}] {pos}/{len} days ({per_sec}, eta {eta})",
+        )?
+        .progress_chars("=> "),
+    );
+    let merging = ProgressStyle::with_template("{elapsed_precise} {spinner} merging {len} days")?;
+    let started = Instant::now();
+    let report = db
+        .copy_rows(rows, batch_size, |copied| {
+            bar.inc(copied as u64);
+            if bar.position() == days {
+                bar.set_style(merging.clone());
+                bar.enable_steady_tick(Duration::from_millis(100));
             }
-            None => 0.0,
-        };
+        })
+        .await;
+    bar.finish_and_clear();
+    let report = report?;
+    let merged = report.inserted + report.updated;
+    let secs = started.elapsed().as_secs_f64();
+    println!(
+        "copied {} day(s) in {:.2}s, {:.0} day(s)/s",
+        merged,
+        secs,
+        merged as f64 / secs.max(f64::EPSILON)
+    );
+    Ok(report)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let filter = FileFilter::new(&cli.include, &cli.exclude)?;
+    let files = archive_files(&cli.dir, &filter)?;
 
-        let stmt = client
-            .prepare("INSERT INTO wakatime_summary (date, editors, langs, machine, projects, depends, grand_total_sec) VALUES ($1, $2, $3, $4, $5, $6, $7)")
-            .await?;
-
-        let grand_total_sec = Decimal::from_f64(grand_total_sec).unwrap();
-        let _ = client
-            .execute(&stmt, &[
-                &log_dt,
-                &editors,
-                &langs,
-                &machines,
-                &projects,
-                &depends,
-                &grand_total_sec
-            ])
-            .await
-            .unwrap_or_else(|_| block_on(update_log(
-                &client,
-                &log_dt,
-                &editors,
-                &langs,
-                &machines,
-                &projects,
-                &depends,
-                &grand_total_sec)).unwrap());
+    let parsed: Vec<(PathBuf, anyhow::Result<ArchiveFile>)> = files
+        .into_par_iter()
+        .map(|path| {
+            let days = read_file(&path);
+            (path, days)
+        })
+        .collect();
+    let mut rows = Vec::new();
+    let mut days = Vec::new();
+    let mut issues = Vec::new();
+    let mut skipped = 0;
+    for (path, file) in parsed {
+        match file {
+            Ok(file) => {
+                println!("read {} day(s) from {}", file.rows.len(), path.display());
+                days.extend(file.rows.iter().map(|row| (row.date, path.clone())));
+                rows.extend(file.rows);
+                issues.extend(file.issues);
+            }
+            Err(err) => {
+                println!("skip {} : {:#}", path.display(), err);
+                skipped += 1;
+            }
+        }
     }
-    let rows = client
-        .query("SELECT $1::TEXT as TXT", &[&"hello world"])
-        .await?;
-    let value: &str = rows[0].get("TXT");
-    println!("{}", value);
 
+    if cli.verify {
+        return verify(&days, issues, skipped);
+    }
+    let db_url = SETTINGS.get_str("db_url")?;
+    if cli.dry_run {
+        return dry_run(&db_url, &days, issues, skipped).await;
+    }
+    let mut db = comdb::connect(&db_url).await?;
+    let report = if cli.copy {
+        copy_rows(&mut db, &rows, cli.batch_size).await?
+    } else {
+        db.upsert_rows(&rows, cli.batch_size).await?
+    };
+    for (date, reason) in &report.failed {
+        println!("failed {} : {}", date, reason);
+    }
+    println!("{}, {} file(s) skipped", report, skipped);
+    // the readable files are stored, but a script has to notice the rest
+    if skipped > 0 || !report.failed.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} file(s) skipped, {} day(s) failed",
+            skipped,
+            report.failed.len()
+        ));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comlib::fixtures;
+
+    #[test]
+    fn splits_range_files_into_days() {
+        let doc = fixtures::summaries_all(
+            ["2021-01-30", "2021-01-31", "2021-02-01"]
+                .iter()
+                .map(|date| fixtures::day(date, 60.0))
+                .collect(),
+        );
+        let path =
+            std::env::temp_dir().join(format!("res_20210130-20210201-{}.json", std::process::id()));
+        std::fs::write(&path, doc.to_string()).unwrap();
+        let file = read_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let file = file.unwrap();
+        let dates: Vec<String> = file.rows.iter().map(|r| r.date.to_string()).collect();
+        assert_eq!(dates, vec!["2021-01-30", "2021-01-31", "2021-02-01"]);
+        let days: Vec<usize> = file
+            .rows
+            .iter()
+            .map(|r| r.data["summaries"]["data"].as_array().unwrap().len())
+            .collect();
+        assert_eq!(days, vec![1, 1, 1]);
+        assert!(file.issues.is_empty());
+    }
+
+    #[test]
+    fn filters_paths_below_the_directory() {
+        let filter = FileFilter::new(
+            &["**/*.json".to_string()],
+            &["old/**".to_string(), "**/*_tmp.json".to_string()],
+        )
+        .unwrap();
+        assert!(filter.matches(Path::new("res_20210101.json")));
+        assert!(filter.matches(Path::new("2021/01/res_20210101-20210131.json")));
+        assert!(!filter.matches(Path::new("old/res_20200101.json")));
+        assert!(!filter.matches(Path::new("2021/res_tmp.json.bak")));
+        assert!(!filter.matches(Path::new("2021/res_20210101_tmp.json")));
+
+        let top_only = FileFilter::new(&["res_*.json".to_string()], &[]).unwrap();
+        assert!(top_only.matches(Path::new("res_20210101.json")));
+        assert!(!top_only.matches(Path::new("2021/res_20210101.json")));
+    }
+}
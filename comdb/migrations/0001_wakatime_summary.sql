@@ -0,0 +1,13 @@
+-- one row per day, registered by get_summary and bulk_regist
+CREATE TABLE IF NOT EXISTS wakatime_summary (
+    date date NOT NULL PRIMARY KEY,
+    editors jsonb NOT NULL DEFAULT '[]',
+    langs jsonb NOT NULL DEFAULT '[]',
+    machine jsonb NOT NULL DEFAULT '[]',
+    projects jsonb NOT NULL DEFAULT '[]',
+    depends jsonb NOT NULL DEFAULT '[]',
+    grand_total_sec numeric NOT NULL DEFAULT 0
+);
+
+-- tables created by hand before there were migrations may lack the key
+CREATE UNIQUE INDEX IF NOT EXISTS wakatime_summary_date_idx ON wakatime_summary (date);
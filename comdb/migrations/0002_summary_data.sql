@@ -0,0 +1,40 @@
+-- the whole SummariesAll document of the day, read by the web server
+ALTER TABLE wakatime_summary ADD COLUMN IF NOT EXISTS data jsonb;
+
+-- the web server used to read a separate, hand made wakatime_dat table
+DO $$
+BEGIN
+    IF to_regclass('wakatime_dat') IS NOT NULL THEN
+        INSERT INTO wakatime_summary AS s
+            (date, editors, langs, machine, projects, depends, grand_total_sec, data)
+        SELECT
+            d.date::date,
+            COALESCE(d.data #> '{summaries,data,0,editors}', '[]'),
+            COALESCE(d.data #> '{summaries,data,0,languages}', '[]'),
+            COALESCE(d.data #> '{summaries,data,0,machines}', '[]'),
+            COALESCE(d.data #> '{summaries,data,0,projects}', '[]'),
+            COALESCE(d.data #> '{summaries,data,0,dependencies}', '[]'),
+            COALESCE((d.data #>> '{summaries,data,0,grand_total,total_seconds}')::numeric, 0),
+            d.data
+        FROM wakatime_dat d
+        ON CONFLICT (date) DO UPDATE SET data = EXCLUDED.data WHERE s.data IS NULL;
+    END IF;
+END $$;
+
+-- days registered before this migration only have the columns, rebuild the
+-- document from them so that the server sees every day
+UPDATE wakatime_summary SET data = jsonb_build_object(
+    'summaries', jsonb_build_object(
+        'data', jsonb_build_array(jsonb_build_object(
+            'editors', editors,
+            'languages', langs,
+            'machines', machine,
+            'projects', projects,
+            'dependencies', depends,
+            'grand_total', jsonb_build_object('total_seconds', grand_total_sec),
+            'range', jsonb_build_object('date', to_char(date, 'YYYY-MM-DD'))
+        ))
+    ),
+    'projects', '{}'::jsonb
+)
+WHERE data IS NULL;
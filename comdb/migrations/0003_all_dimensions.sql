@@ -0,0 +1,49 @@
+-- dimensions that used to be kept only inside the data document
+ALTER TABLE wakatime_summary
+    ADD COLUMN IF NOT EXISTS categories jsonb NOT NULL DEFAULT '[]',
+    ADD COLUMN IF NOT EXISTS operating_systems jsonb NOT NULL DEFAULT '[]',
+    ADD COLUMN IF NOT EXISTS range jsonb NOT NULL DEFAULT '{}';
+
+-- per-project breakdown of each day, replaced together with the day
+CREATE TABLE IF NOT EXISTS wakatime_project_summary (
+    date date NOT NULL REFERENCES wakatime_summary (date) ON DELETE CASCADE,
+    project text NOT NULL,
+    branches jsonb NOT NULL DEFAULT '[]',
+    entities jsonb NOT NULL DEFAULT '[]',
+    categories jsonb NOT NULL DEFAULT '[]',
+    editors jsonb NOT NULL DEFAULT '[]',
+    langs jsonb NOT NULL DEFAULT '[]',
+    machine jsonb NOT NULL DEFAULT '[]',
+    depends jsonb NOT NULL DEFAULT '[]',
+    operating_systems jsonb NOT NULL DEFAULT '[]',
+    grand_total_sec numeric NOT NULL DEFAULT 0,
+    PRIMARY KEY (date, project)
+);
+
+UPDATE wakatime_summary SET
+    categories = COALESCE(data #> '{summaries,data,0,categories}', '[]'),
+    operating_systems = COALESCE(data #> '{summaries,data,0,operating_system}', '[]'),
+    range = COALESCE(data #> '{summaries,data,0,range}', '{}')
+WHERE data IS NOT NULL;
+
+INSERT INTO wakatime_project_summary
+    (date, project, branches, entities, categories, editors, langs, machine,
+     depends, operating_systems, grand_total_sec)
+SELECT
+    s.date,
+    p.key,
+    COALESCE(p.value #> '{data,0,branches}', '[]'),
+    COALESCE(p.value #> '{data,0,entities}', '[]'),
+    COALESCE(p.value #> '{data,0,categories}', '[]'),
+    COALESCE(p.value #> '{data,0,editors}', '[]'),
+    COALESCE(p.value #> '{data,0,languages}', '[]'),
+    COALESCE(p.value #> '{data,0,machines}', '[]'),
+    COALESCE(p.value #> '{data,0,dependencies}', '[]'),
+    COALESCE(p.value #> '{data,0,operating_system}', '[]'),
+    COALESCE((p.value #>> '{data,0,grand_total,total_seconds}')::numeric, 0)
+FROM wakatime_summary s
+CROSS JOIN LATERAL jsonb_each(
+    CASE jsonb_typeof(s.data -> 'projects') WHEN 'object' THEN s.data -> 'projects' ELSE '{}' END
+) p
+WHERE p.value #> '{data,0}' IS NOT NULL
+ON CONFLICT (date, project) DO NOTHING;
@@ -0,0 +1,14 @@
+-- one row per (day, dimension, name), project is '' for the totals of the
+-- day and the project name for the per-project breakdown
+CREATE TABLE IF NOT EXISTS wakatime_fact (
+    date date NOT NULL REFERENCES wakatime_summary (date) ON DELETE CASCADE,
+    dimension text NOT NULL,
+    name text NOT NULL,
+    project text NOT NULL DEFAULT '',
+    seconds double precision NOT NULL,
+    PRIMARY KEY (date, dimension, project, name)
+);
+
+-- rankings filter on the dimension and a date range
+CREATE INDEX IF NOT EXISTS wakatime_fact_dimension_date_idx
+    ON wakatime_fact (dimension, project, date);
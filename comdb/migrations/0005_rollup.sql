@@ -0,0 +1,14 @@
+-- wakatime_fact summed per ISO week, calendar month and year
+CREATE TABLE IF NOT EXISTS wakatime_rollup (
+    period text NOT NULL CHECK (period IN ('week', 'month', 'year')),
+    period_start date NOT NULL,
+    period_end date NOT NULL,
+    dimension text NOT NULL,
+    name text NOT NULL,
+    project text NOT NULL DEFAULT '',
+    seconds double precision NOT NULL,
+    PRIMARY KEY (period, period_start, dimension, project, name)
+);
+
+CREATE INDEX IF NOT EXISTS wakatime_rollup_dimension_idx
+    ON wakatime_rollup (period, dimension, project, period_start);
@@ -0,0 +1,12 @@
+-- one row per day, the same table the sqlite sink of get_summary used to
+-- create; JSON columns are stored as text
+CREATE TABLE IF NOT EXISTS wakatime_summary (
+    date TEXT NOT NULL PRIMARY KEY,
+    editors TEXT NOT NULL DEFAULT '[]',
+    langs TEXT NOT NULL DEFAULT '[]',
+    machine TEXT NOT NULL DEFAULT '[]',
+    projects TEXT NOT NULL DEFAULT '[]',
+    depends TEXT NOT NULL DEFAULT '[]',
+    grand_total_sec REAL NOT NULL DEFAULT 0,
+    data TEXT
+);
@@ -0,0 +1,17 @@
+-- the data column is part of the table from the start, only rebuild the
+-- document of rows that lack it
+UPDATE wakatime_summary SET data = json_object(
+    'summaries', json_object(
+        'data', json_array(json_object(
+            'editors', json(editors),
+            'languages', json(langs),
+            'machines', json(machine),
+            'projects', json(projects),
+            'dependencies', json(depends),
+            'grand_total', json_object('total_seconds', grand_total_sec),
+            'range', json_object('date', date)
+        ))
+    ),
+    'projects', json_object()
+)
+WHERE data IS NULL;
@@ -0,0 +1,44 @@
+-- dimensions that used to be kept only inside the data document
+ALTER TABLE wakatime_summary ADD COLUMN categories TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE wakatime_summary ADD COLUMN operating_systems TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE wakatime_summary ADD COLUMN range TEXT NOT NULL DEFAULT '{}';
+
+-- per-project breakdown of each day, replaced together with the day
+CREATE TABLE IF NOT EXISTS wakatime_project_summary (
+    date TEXT NOT NULL REFERENCES wakatime_summary (date) ON DELETE CASCADE,
+    project TEXT NOT NULL,
+    branches TEXT NOT NULL DEFAULT '[]',
+    entities TEXT NOT NULL DEFAULT '[]',
+    categories TEXT NOT NULL DEFAULT '[]',
+    editors TEXT NOT NULL DEFAULT '[]',
+    langs TEXT NOT NULL DEFAULT '[]',
+    machine TEXT NOT NULL DEFAULT '[]',
+    depends TEXT NOT NULL DEFAULT '[]',
+    operating_systems TEXT NOT NULL DEFAULT '[]',
+    grand_total_sec REAL NOT NULL DEFAULT 0,
+    PRIMARY KEY (date, project)
+);
+
+UPDATE wakatime_summary SET
+    categories = COALESCE(json_extract(data, '$.summaries.data[0].categories'), '[]'),
+    operating_systems = COALESCE(json_extract(data, '$.summaries.data[0].operating_system'), '[]'),
+    range = COALESCE(json_extract(data, '$.summaries.data[0].range'), '{}')
+WHERE data IS NOT NULL;
+
+INSERT OR IGNORE INTO wakatime_project_summary
+    (date, project, branches, entities, categories, editors, langs, machine,
+     depends, operating_systems, grand_total_sec)
+SELECT
+    s.date,
+    p.key,
+    COALESCE(json_extract(p.value, '$.data[0].branches'), '[]'),
+    COALESCE(json_extract(p.value, '$.data[0].entities'), '[]'),
+    COALESCE(json_extract(p.value, '$.data[0].categories'), '[]'),
+    COALESCE(json_extract(p.value, '$.data[0].editors'), '[]'),
+    COALESCE(json_extract(p.value, '$.data[0].languages'), '[]'),
+    COALESCE(json_extract(p.value, '$.data[0].machines'), '[]'),
+    COALESCE(json_extract(p.value, '$.data[0].dependencies'), '[]'),
+    COALESCE(json_extract(p.value, '$.data[0].operating_system'), '[]'),
+    COALESCE(json_extract(p.value, '$.data[0].grand_total.total_seconds'), 0)
+FROM wakatime_summary s, json_each(s.data, '$.projects') p
+WHERE json_type(p.value, '$.data[0]') = 'object';
@@ -0,0 +1,14 @@
+-- one row per (day, dimension, name), project is '' for the totals of the
+-- day and the project name for the per-project breakdown
+CREATE TABLE IF NOT EXISTS wakatime_fact (
+    date TEXT NOT NULL REFERENCES wakatime_summary (date) ON DELETE CASCADE,
+    dimension TEXT NOT NULL,
+    name TEXT NOT NULL,
+    project TEXT NOT NULL DEFAULT '',
+    seconds REAL NOT NULL,
+    PRIMARY KEY (date, dimension, project, name)
+);
+
+-- rankings filter on the dimension and a date range
+CREATE INDEX IF NOT EXISTS wakatime_fact_dimension_date_idx
+    ON wakatime_fact (dimension, project, date);
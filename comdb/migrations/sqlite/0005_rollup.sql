@@ -0,0 +1,14 @@
+-- wakatime_fact summed per ISO week, calendar month and year
+CREATE TABLE IF NOT EXISTS wakatime_rollup (
+    period TEXT NOT NULL CHECK (period IN ('week', 'month', 'year')),
+    period_start TEXT NOT NULL,
+    period_end TEXT NOT NULL,
+    dimension TEXT NOT NULL,
+    name TEXT NOT NULL,
+    project TEXT NOT NULL DEFAULT '',
+    seconds REAL NOT NULL,
+    PRIMARY KEY (period, period_start, dimension, project, name)
+);
+
+CREATE INDEX IF NOT EXISTS wakatime_rollup_dimension_idx
+    ON wakatime_rollup (period, dimension, project, period_start);
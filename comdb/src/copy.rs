@@ -0,0 +1,250 @@
+//! Bulk loading through `COPY ... FROM STDIN` for imports of whole archives.
+
+use std::collections::BTreeMap;
+
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::{Client, Transaction};
+
+use crate::rollup::{affected_periods, RollupStatements};
+use crate::summary::numeric;
+use crate::{fact, DbError, SummaryRow, UpsertReport};
+
+/// Staging tables, dropped with the transaction that loads them.
+const CREATE_STAGING: &str = r#"
+CREATE TEMP TABLE wakatime_summary_staging
+    (LIKE wakatime_summary INCLUDING DEFAULTS) ON COMMIT DROP;
+CREATE TEMP TABLE wakatime_project_summary_staging
+    (LIKE wakatime_project_summary INCLUDING DEFAULTS) ON COMMIT DROP;
+CREATE TEMP TABLE wakatime_fact_staging
+    (LIKE wakatime_fact INCLUDING DEFAULTS) ON COMMIT DROP"#;
+
+const COPY_SUMMARY: &str = r#"
+COPY wakatime_summary_staging
+    (date, categories, editors, langs, machine, operating_systems, projects,
+     depends, range, grand_total_sec, data)
+FROM STDIN BINARY"#;
+
+const COPY_PROJECT: &str = r#"
+COPY wakatime_project_summary_staging
+    (date, project, branches, entities, categories, editors, langs, machine,
+     depends, operating_systems, grand_total_sec)
+FROM STDIN BINARY"#;
+
+const COPY_FACT: &str = r#"
+COPY wakatime_fact_staging (date, dimension, name, project, seconds)
+FROM STDIN BINARY"#;
+
+/// Upserts the staged days, returning how many were inserted and updated.
+const MERGE_SUMMARY: &str = r#"
+WITH merged AS (
+    INSERT INTO wakatime_summary AS s
+        (date, categories, editors, langs, machine, operating_systems, projects,
+         depends, range, grand_total_sec, data)
+    SELECT date, categories, editors, langs, machine, operating_systems, projects,
+        depends, range, grand_total_sec, data
+    FROM wakatime_summary_staging
+    ON CONFLICT (date) DO UPDATE SET
+        categories = EXCLUDED.categories,
+        editors = EXCLUDED.editors,
+        langs = EXCLUDED.langs,
+        machine = EXCLUDED.machine,
+        operating_systems = EXCLUDED.operating_systems,
+        projects = EXCLUDED.projects,
+        depends = EXCLUDED.depends,
+        range = EXCLUDED.range,
+        grand_total_sec = EXCLUDED.grand_total_sec,
+        data = EXCLUDED.data
+    RETURNING (xmax = 0) AS inserted
+)
+SELECT count(*) FILTER (WHERE inserted), count(*) FILTER (WHERE NOT inserted)
+FROM merged"#;
+
+/// Replaces the projects and facts of the staged days.
+const MERGE_DETAILS: &str = r#"
+DELETE FROM wakatime_project_summary
+WHERE date IN (SELECT date FROM wakatime_summary_staging);
+INSERT INTO wakatime_project_summary
+    (date, project, branches, entities, categories, editors, langs, machine,
+     depends, operating_systems, grand_total_sec)
+SELECT date, project, branches, entities, categories, editors, langs, machine,
+    depends, operating_systems, grand_total_sec
+FROM wakatime_project_summary_staging;
+DELETE FROM wakatime_fact
+WHERE date IN (SELECT date FROM wakatime_summary_staging);
+INSERT INTO wakatime_fact (date, dimension, name, project, seconds)
+SELECT date, dimension, name, project, seconds FROM wakatime_fact_staging"#;
+
+/// Inserts `rows` like [`crate::summary::upsert_rows`], streaming them into
+/// staging tables with `COPY` and merging them with a few statements.
+///
+/// Everything happens in one transaction: a day the database rejects rolls
+/// back the whole import and is returned as an error, so
+/// [`UpsertReport::failed`] is always empty. When a date appears more than
+/// once the last row wins. `progress` is called with the number of days
+/// copied after every `batch_size` days.
+pub async fn copy_rows(
+    client: &mut Client,
+    rows: &[SummaryRow],
+    batch_size: usize,
+    mut progress: impl FnMut(usize),
+) -> Result<UpsertReport, DbError> {
+    let rows = latest_per_date(rows);
+    let tx = client.transaction().await?;
+    tx.batch_execute(CREATE_STAGING).await?;
+    for batch in rows.chunks(batch_size.max(1)) {
+        copy_batch(&tx, batch).await?;
+        progress(batch.len());
+    }
+
+    let counts = tx.query_one(MERGE_SUMMARY, &[]).await?;
+    tx.batch_execute(MERGE_DETAILS).await?;
+    let rollups = RollupStatements::prepare(&tx).await?;
+    rollups
+        .refresh(&tx, &affected_periods(rows.iter().map(|row| row.date)))
+        .await?;
+    tx.commit().await?;
+
+    let inserted: i64 = counts.get(0);
+    let updated: i64 = counts.get(1);
+    Ok(UpsertReport {
+        inserted: inserted as usize,
+        updated: updated as usize,
+        failed: Vec::new(),
+    })
+}
+
+/// One row per date, the last one when a date appears more than once, by date.
+pub(crate) fn latest_per_date(rows: &[SummaryRow]) -> Vec<&SummaryRow> {
+    rows.iter()
+        .map(|row| (row.date, row))
+        .collect::<BTreeMap<_, _>>()
+        .into_values()
+        .collect()
+}
+
+/// Streams the days, projects and facts of `batch` into the staging tables.
+async fn copy_batch(tx: &Transaction<'_>, batch: &[&SummaryRow]) -> Result<(), DbError> {
+    let sink = tx.copy_in(COPY_SUMMARY).await?;
+    let mut writer = Box::pin(BinaryCopyInWriter::new(
+        sink,
+        &[
+            Type::DATE,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::NUMERIC,
+            Type::JSONB,
+        ],
+    ));
+    for row in batch {
+        let grand_total_sec = numeric(row.grand_total_sec);
+        let values: [&(dyn ToSql + Sync); 11] = [
+            &row.date,
+            &row.categories,
+            &row.editors,
+            &row.langs,
+            &row.machines,
+            &row.operating_systems,
+            &row.projects,
+            &row.depends,
+            &row.range,
+            &grand_total_sec,
+            &row.data,
+        ];
+        writer.as_mut().write(&values).await?;
+    }
+    writer.as_mut().finish().await?;
+
+    let sink = tx.copy_in(COPY_PROJECT).await?;
+    let mut writer = Box::pin(BinaryCopyInWriter::new(
+        sink,
+        &[
+            Type::DATE,
+            Type::TEXT,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::JSONB,
+            Type::NUMERIC,
+        ],
+    ));
+    for row in batch {
+        for proj in &row.project_rows {
+            let grand_total_sec = numeric(proj.grand_total_sec);
+            let values: [&(dyn ToSql + Sync); 11] = [
+                &row.date,
+                &proj.project,
+                &proj.branches,
+                &proj.entities,
+                &proj.categories,
+                &proj.editors,
+                &proj.langs,
+                &proj.machines,
+                &proj.depends,
+                &proj.operating_systems,
+                &grand_total_sec,
+            ];
+            writer.as_mut().write(&values).await?;
+        }
+    }
+    writer.as_mut().finish().await?;
+
+    let sink = tx.copy_in(COPY_FACT).await?;
+    let mut writer = Box::pin(BinaryCopyInWriter::new(
+        sink,
+        &[Type::DATE, Type::TEXT, Type::TEXT, Type::TEXT, Type::FLOAT8],
+    ));
+    for row in batch {
+        for f in fact::facts(row) {
+            let values: [&(dyn ToSql + Sync); 5] = [
+                &row.date,
+                &f.dimension,
+                &f.name,
+                &f.project,
+                &f.seconds.as_f64(),
+            ];
+            writer.as_mut().write(&values).await?;
+        }
+    }
+    writer.as_mut().finish().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, NaiveDate};
+    use serde_json::json;
+
+    #[test]
+    fn keeps_the_last_row_of_each_date() {
+        let row = |d: u32, tag: &str| {
+            SummaryRow::from_value(
+                NaiveDate::from_ymd_opt(2021, 2, d).unwrap(),
+                json!({ "tag": tag }),
+            )
+        };
+        let rows = vec![
+            row(23, "first"),
+            row(22, "only"),
+            row(23, "second"),
+            row(24, "only"),
+            row(23, "last"),
+        ];
+        let kept: Vec<(u32, &str)> = latest_per_date(&rows)
+            .into_iter()
+            .map(|row| (row.date.day(), row.data["tag"].as_str().unwrap()))
+            .collect();
+        assert_eq!(kept, vec![(22, "only"), (23, "last"), (24, "only")]);
+    }
+}
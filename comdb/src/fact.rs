@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use comlib::{Seconds, SummariesData};
+use serde_json::Value;
+use tokio_postgres::{Client, GenericClient, Statement};
+
+use crate::{DbError, SummaryRow};
+
+/// Version of the migration creating `wakatime_fact`, the table is filled
+/// from the stored days right after it is applied.
+pub(crate) const FACT_VERSION: i32 = 4;
+
+/// Inserts the facts of one day given as parallel arrays.
+pub(crate) const INSERT_FACTS: &str = r#"
+INSERT INTO wakatime_fact (date, dimension, name, project, seconds)
+SELECT $1, * FROM unnest($2::text[], $3::text[], $4::text[], $5::float8[])"#;
+
+pub(crate) const CLEAR_FACTS: &str = "DELETE FROM wakatime_fact WHERE date = $1";
+
+/// One row of the `wakatime_fact` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fact {
+    /// One of [`SummariesData::DIMENSIONS`], `branches` and `entities` only
+    /// for projects
+    pub dimension: &'static str,
+    pub name: String,
+    /// Empty for the totals of the whole day
+    pub project: String,
+    pub seconds: Seconds,
+}
+
+/// Facts of one day, entries with the same name are summed.
+pub fn facts(row: &SummaryRow) -> Vec<Fact> {
+    let mut sums: BTreeMap<(&'static str, &str, &str), Seconds> = BTreeMap::new();
+    for dimension in SummariesData::DIMENSIONS {
+        if let Some(values) = row.dimension(dimension) {
+            collect(&mut sums, dimension, "", values);
+        }
+        for proj in &row.project_rows {
+            if let Some(values) = proj.dimension(dimension) {
+                collect(&mut sums, dimension, &proj.project, values);
+            }
+        }
+    }
+    sums.into_iter()
+        .map(|((dimension, project, name), seconds)| Fact {
+            dimension,
+            name: name.to_string(),
+            project: project.to_string(),
+            seconds,
+        })
+        .collect()
+}
+
+fn collect<'a>(
+    sums: &mut BTreeMap<(&'static str, &'a str, &'a str), Seconds>,
+    dimension: &'static str,
+    project: &'a str,
+    values: &'a Value,
+) {
+    for item in values.as_array().into_iter().flatten() {
+        if let Some(name) = item["name"].as_str() {
+            let seconds = Seconds(item["total_seconds"].as_f64().unwrap_or(0.0));
+            *sums.entry((dimension, project, name)).or_default() += seconds;
+        }
+    }
+}
+
+/// Replaces the facts of `date` using a statement prepared from [`INSERT_FACTS`].
+pub(crate) async fn replace_facts<C: GenericClient>(
+    client: &C,
+    clear: &Statement,
+    insert: &Statement,
+    date: NaiveDate,
+    facts: &[Fact],
+) -> Result<(), tokio_postgres::Error> {
+    client.execute(clear, &[&date]).await?;
+    let dimensions: Vec<&str> = facts.iter().map(|f| f.dimension).collect();
+    let names: Vec<&str> = facts.iter().map(|f| f.name.as_str()).collect();
+    let projects: Vec<&str> = facts.iter().map(|f| f.project.as_str()).collect();
+    let seconds: Vec<f64> = facts.iter().map(|f| f.seconds.as_f64()).collect();
+    client
+        .execute(insert, &[&date, &dimensions, &names, &projects, &seconds])
+        .await?;
+    Ok(())
+}
+
+/// Refills `wakatime_fact` from the `data` document of every stored day,
+/// reading `batch_size` days at a time. Returns the number of days.
+pub async fn rebuild_facts(client: &mut Client, batch_size: usize) -> Result<usize, DbError> {
+    let tx = client.transaction().await?;
+    tx.batch_execute("DELETE FROM wakatime_fact").await?;
+    let clear = tx.prepare(CLEAR_FACTS).await?;
+    let insert = tx.prepare(INSERT_FACTS).await?;
+    let select = tx
+        .prepare("SELECT date, data FROM wakatime_summary WHERE data IS NOT NULL ORDER BY date")
+        .await?;
+    let portal = tx.bind(&select, &[]).await?;
+    let mut days = 0;
+    loop {
+        let batch = tx
+            .query_portal(&portal, batch_size.clamp(1, i32::MAX as usize) as i32)
+            .await?;
+        if batch.is_empty() {
+            break;
+        }
+        for stored in batch {
+            let row = SummaryRow::from_value(stored.get(0), stored.get(1));
+            replace_facts(&tx, &clear, &insert, row.date, &facts(&row)).await?;
+            days += 1;
+        }
+    }
+    tx.commit().await?;
+    Ok(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn sums_facts_by_name() {
+        let item = |name: &str, sec: f64| json!({ "name": name, "total_seconds": sec });
+        let doc = json!({
+            "summaries": { "data": [{
+                "editors": [item("vim", 60.0)],
+                "machines": [item("pc", 30.0), item("pc", 20.0)],
+                "projects": [item("crate", 60.0), { "total_seconds": 1.0 }]
+            }] },
+            "projects": { "crate": { "data": [{
+                "branches": [item("main", 40.0), item("dev", 20.0)]
+            }] } }
+        });
+        let row = SummaryRow::from_value(NaiveDate::from_ymd_opt(2021, 2, 22).unwrap(), doc);
+        let facts = facts(&row);
+        let find = |dimension: &str, project: &str, name: &str| {
+            facts
+                .iter()
+                .find(|f| f.dimension == dimension && f.project == project && f.name == name)
+                .map(|f| f.seconds.as_f64())
+        };
+        assert_eq!(facts.len(), 5);
+        assert_eq!(find("machines", "", "pc"), Some(50.0));
+        assert_eq!(find("projects", "", "crate"), Some(60.0));
+        assert_eq!(find("branches", "crate", "dev"), Some(20.0));
+        assert_eq!(find("branches", "", "dev"), None);
+    }
+    #[test]
+    fn every_dimension_has_a_column() {
+        let doc = json!({
+            "summaries": { "data": [{}] },
+            "projects": { "crate": { "data": [{}] } }
+        });
+        let row = SummaryRow::from_value(NaiveDate::from_ymd_opt(2021, 2, 22).unwrap(), doc);
+        for dimension in SummariesData::DIMENSIONS {
+            assert!(
+                row.dimension(dimension).is_some()
+                    || row.project_rows[0].dimension(dimension).is_some(),
+                "{}",
+                dimension
+            );
+        }
+    }
+}
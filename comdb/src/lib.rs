@@ -0,0 +1,369 @@
+//! Database schema shared by get_summary, bulk_regist and the web server.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use chrono::NaiveDate;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio_postgres::{Client, NoTls};
+
+pub mod copy;
+pub mod fact;
+pub mod migrate;
+pub mod rollup;
+pub mod sqlite;
+pub mod summary;
+
+pub use fact::{facts, Fact};
+pub use migrate::{check_schema_version, SCHEMA_VERSION, SCHEMA_VERSION_QUERY};
+pub use rollup::Period;
+pub use summary::{ProjectRow, SummaryRow, UpsertReport, DEFAULT_BATCH_SIZE};
+
+/// Connection to the summary database, PostgreSQL or a SQLite file depending
+/// on the `db_url` it was opened with.
+pub enum Database {
+    Postgres(Client),
+    Sqlite(Mutex<rusqlite::Connection>),
+}
+
+/// Path of a SQLite `db_url`: `sqlite://wakatime.db`, `sqlite:wakatime.db` or
+/// `sqlite::memory:`. `None` for any other URL, which is PostgreSQL.
+pub fn sqlite_path(db_url: &str) -> Option<&str> {
+    let rest = db_url.strip_prefix("sqlite:")?;
+    let rest = rest.strip_prefix("//").unwrap_or(rest);
+    rest.split('?').next()
+}
+
+async fn connect_postgres(db_url: &str) -> Result<Client, DbError> {
+    let (client, connection) = tokio_postgres::connect(db_url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            println!("connection error: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+/// Connects to `db_url` without touching the schema, for a look at what is
+/// stored before deciding to write.
+///
+/// Nothing can be written through it: a SQLite file is opened read-only and
+/// the PostgreSQL session is made read-only. `None` when the SQLite file does
+/// not exist yet, so that looking does not create it.
+pub async fn open(db_url: &str) -> Result<Option<Database>, DbError> {
+    Ok(match sqlite_path(db_url) {
+        Some(path) => blocking(|| sqlite::open_read_only(path))?
+            .map(|conn| Database::Sqlite(Mutex::new(conn))),
+        None => {
+            let client = connect_postgres(db_url).await?;
+            client
+                .batch_execute("SET default_transaction_read_only = on")
+                .await?;
+            Some(Database::Postgres(client))
+        }
+    })
+}
+
+/// Connects to `db_url` and migrates the schema to [`SCHEMA_VERSION`]. A
+/// SQLite file is created when missing.
+pub async fn connect(db_url: &str) -> Result<Database, DbError> {
+    let mut db = match sqlite_path(db_url) {
+        Some(path) => Database::Sqlite(Mutex::new(blocking(|| sqlite::open(path))?)),
+        None => Database::Postgres(connect_postgres(db_url).await?),
+    };
+    let applied = db.migrate().await?;
+    for version in &applied {
+        println!("schema migrated to version {}", version);
+    }
+    if applied.contains(&fact::FACT_VERSION) {
+        let days = db.rebuild_facts(DEFAULT_BATCH_SIZE).await?;
+        println!("wakatime_fact filled from {} day(s)", days);
+    }
+    if applied.contains(&rollup::ROLLUP_VERSION) {
+        let periods = db.rebuild_rollups().await?;
+        println!("wakatime_rollup filled with {} period(s)", periods);
+    }
+    Ok(db)
+}
+
+/// Runs blocking work from async code, handing the other tasks of this worker
+/// to another thread first when the runtime has several.
+fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(f),
+        _ => f(),
+    }
+}
+
+impl Database {
+    /// Runs `f` on the SQLite connection, see [`blocking`].
+    fn with_sqlite<T>(
+        conn: &Mutex<rusqlite::Connection>,
+        f: impl FnOnce(&mut rusqlite::Connection) -> T,
+    ) -> T {
+        blocking(|| f(&mut conn.lock().expect("sqlite connection poisoned")))
+    }
+
+    /// Version read with [`SCHEMA_VERSION_QUERY`], `None` when the database
+    /// was never migrated.
+    pub async fn schema_version(&self) -> Option<i32> {
+        match self {
+            Database::Postgres(client) => client
+                .query_one(SCHEMA_VERSION_QUERY, &[])
+                .await
+                .ok()
+                .and_then(|row| row.get(0)),
+            Database::Sqlite(conn) => Database::with_sqlite(conn, |conn| {
+                conn.query_row(SCHEMA_VERSION_QUERY, [], |row| row.get(0))
+                    .unwrap_or(None)
+            }),
+        }
+    }
+
+    /// Applies the pending migrations, returning their versions.
+    pub async fn migrate(&mut self) -> Result<Vec<i32>, DbError> {
+        match self {
+            Database::Postgres(client) => migrate::migrate(client).await,
+            Database::Sqlite(conn) => Database::with_sqlite(conn, sqlite::migrate),
+        }
+    }
+
+    /// Inserts `rows`, replacing the days that are already stored, see
+    /// [`summary::upsert_rows`].
+    pub async fn upsert_rows(
+        &mut self,
+        rows: &[SummaryRow],
+        batch_size: usize,
+    ) -> Result<UpsertReport, DbError> {
+        match self {
+            Database::Postgres(client) => summary::upsert_rows(client, rows, batch_size).await,
+            Database::Sqlite(conn) => {
+                Database::with_sqlite(conn, |conn| sqlite::upsert_rows(conn, rows, batch_size))
+            }
+        }
+    }
+
+    /// [`Database::upsert_rows`] for large imports: PostgreSQL loads all the
+    /// rows in one transaction through `COPY`, see [`copy::copy_rows`], SQLite
+    /// has no `COPY` and upserts `batch_size` days per transaction.
+    ///
+    /// Both keep the last row of a date given more than once. `progress` is
+    /// called with the number of days written after every batch; on
+    /// PostgreSQL the merge into the tables still follows the last call.
+    pub async fn copy_rows(
+        &mut self,
+        rows: &[SummaryRow],
+        batch_size: usize,
+        mut progress: impl FnMut(usize),
+    ) -> Result<UpsertReport, DbError> {
+        match self {
+            Database::Postgres(client) => copy::copy_rows(client, rows, batch_size, progress).await,
+            Database::Sqlite(conn) => Database::with_sqlite(conn, |conn| {
+                let rows = copy::latest_per_date(rows);
+                let mut report = UpsertReport::default();
+                for batch in rows.chunks(batch_size.max(1)) {
+                    let done = sqlite::upsert_rows(conn, batch, batch.len())?;
+                    report.inserted += done.inserted;
+                    report.updated += done.updated;
+                    report.failed.extend(done.failed);
+                    progress(batch.len());
+                }
+                Ok(report)
+            }),
+        }
+    }
+
+    /// Refills `wakatime_fact` from the stored documents, returning the
+    /// number of days.
+    pub async fn rebuild_facts(&mut self, batch_size: usize) -> Result<usize, DbError> {
+        match self {
+            Database::Postgres(client) => fact::rebuild_facts(client, batch_size).await,
+            Database::Sqlite(conn) => Database::with_sqlite(conn, sqlite::rebuild_facts),
+        }
+    }
+
+    /// Recomputes every rollup, returning the number of periods.
+    pub async fn rebuild_rollups(&mut self) -> Result<usize, DbError> {
+        match self {
+            Database::Postgres(client) => rollup::rebuild_rollups(client).await,
+            Database::Sqlite(conn) => Database::with_sqlite(conn, sqlite::rebuild_rollups),
+        }
+    }
+
+    /// Dates stored from `from` on.
+    pub async fn stored_dates(&self, from: NaiveDate) -> Result<BTreeSet<NaiveDate>, DbError> {
+        match self {
+            Database::Postgres(client) => summary::stored_dates(client, from).await,
+            Database::Sqlite(conn) => {
+                Database::with_sqlite(conn, |conn| sqlite::stored_dates(conn, from))
+            }
+        }
+    }
+
+    /// Days stored in `from..=to`, read back from their `data` document.
+    pub async fn stored_rows(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<SummaryRow>, DbError> {
+        match self {
+            Database::Postgres(client) => summary::stored_rows(client, from, to).await,
+            Database::Sqlite(conn) => {
+                Database::with_sqlite(conn, |conn| sqlite::stored_rows(conn, from, to))
+            }
+        }
+    }
+}
+
+/// Error of the database access
+#[derive(Debug)]
+pub enum DbError {
+    Postgres(tokio_postgres::Error),
+    Sqlite(rusqlite::Error),
+    Json(serde_json::Error),
+    Date(chrono::ParseError),
+    /// The database has no `schema_migrations` table yet
+    SchemaMissing,
+    /// The database was not migrated to the version this build expects
+    SchemaOutdated {
+        found: i32,
+        expected: i32,
+    },
+    /// The database was migrated by a newer build
+    SchemaTooNew {
+        found: i32,
+        supported: i32,
+    },
+}
+
+impl Error for DbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DbError::Postgres(error) => Some(error),
+            DbError::Sqlite(error) => Some(error),
+            DbError::Json(error) => Some(error),
+            DbError::Date(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            DbError::Postgres(error) => write!(f, "postgres error: {}", error),
+            DbError::Sqlite(error) => write!(f, "sqlite error: {}", error),
+            DbError::Json(error) => write!(f, "json error: {}", error),
+            DbError::Date(error) => write!(f, "invalid date: {}", error),
+            DbError::SchemaMissing => write!(
+                f,
+                "database schema not found, run get_summary or bulk_regist once to create it"
+            ),
+            DbError::SchemaOutdated { found, expected } => write!(
+                f,
+                "database schema version {} is older than {}, run get_summary or bulk_regist to migrate it",
+                found, expected
+            ),
+            DbError::SchemaTooNew { found, supported } => write!(
+                f,
+                "database schema version {} is newer than the supported {}, update this binary",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for DbError {
+    fn from(error: tokio_postgres::Error) -> Self {
+        DbError::Postgres(error)
+    }
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(error: rusqlite::Error) -> Self {
+        DbError::Sqlite(error)
+    }
+}
+
+impl From<serde_json::Error> for DbError {
+    fn from(error: serde_json::Error) -> Self {
+        DbError::Json(error)
+    }
+}
+
+impl From<chrono::ParseError> for DbError {
+    fn from(error: chrono::ParseError) -> Self {
+        DbError::Date(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_sqlite_urls() {
+        assert_eq!(sqlite_path("sqlite://wakatime.db"), Some("wakatime.db"));
+        assert_eq!(
+            sqlite_path("sqlite:///var/lib/waka.db"),
+            Some("/var/lib/waka.db")
+        );
+        assert_eq!(
+            sqlite_path("sqlite:wakatime.db?mode=rwc"),
+            Some("wakatime.db")
+        );
+        assert_eq!(sqlite_path("sqlite::memory:"), Some(":memory:"));
+        assert_eq!(sqlite_path("host=localhost user=postgres"), None);
+        assert_eq!(sqlite_path("postgres://localhost/waka"), None);
+    }
+
+    async fn migrated_sqlite() -> Database {
+        let db = connect("sqlite::memory:").await.unwrap();
+        assert_eq!(db.schema_version().await, Some(SCHEMA_VERSION));
+        db
+    }
+
+    #[tokio::test]
+    async fn runs_sqlite_on_a_current_thread_runtime() {
+        let db = migrated_sqlite().await;
+        assert!(db.stored_dates(NaiveDate::MIN).await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn runs_sqlite_on_a_multi_thread_runtime() {
+        let db = migrated_sqlite().await;
+        assert!(db.stored_dates(NaiveDate::MIN).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn copies_into_sqlite_in_batches() {
+        let mut db = migrated_sqlite().await;
+        let row = |d: u32, seconds: f64| {
+            SummaryRow::from_value(
+                NaiveDate::from_ymd_opt(2021, 2, d).unwrap(),
+                serde_json::json!({ "grand_total": { "total_seconds": seconds } }),
+            )
+        };
+        let rows = vec![
+            row(22, 60.0),
+            row(23, 60.0),
+            row(24, 60.0),
+            row(23, 90.0),
+            row(25, 60.0),
+        ];
+        let mut batches = Vec::new();
+        let report = db
+            .copy_rows(&rows, 2, |days| batches.push(days))
+            .await
+            .unwrap();
+        assert_eq!(batches, vec![2, 2]);
+        assert_eq!((report.inserted, report.updated), (4, 0));
+
+        let day = NaiveDate::from_ymd_opt(2021, 2, 23).unwrap();
+        let stored = db.stored_rows(day, day).await.unwrap();
+        assert_eq!(stored, vec![row(23, 90.0)]);
+    }
+}
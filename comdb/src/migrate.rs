@@ -0,0 +1,159 @@
+use std::collections::BTreeSet;
+
+use tokio_postgres::Client;
+
+use crate::DbError;
+
+/// One step of the schema, applied once and recorded in `schema_migrations`.
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every migration in the order it is applied.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "wakatime_summary",
+        sql: include_str!("../migrations/0001_wakatime_summary.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "summary_data",
+        sql: include_str!("../migrations/0002_summary_data.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "all_dimensions",
+        sql: include_str!("../migrations/0003_all_dimensions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "fact",
+        sql: include_str!("../migrations/0004_fact.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "rollup",
+        sql: include_str!("../migrations/0005_rollup.sql"),
+    },
+];
+
+/// Version of the schema this build reads and writes.
+pub const SCHEMA_VERSION: i32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Query returning the version of a migrated database, usable from any client.
+pub const SCHEMA_VERSION_QUERY: &str = "SELECT max(version) FROM schema_migrations";
+
+/// Any number works as long as every binary uses the same one.
+const MIGRATION_LOCK: i64 = 0x7761_6b61;
+
+/// Brings the database up to [`SCHEMA_VERSION`], returning the versions applied.
+///
+/// Runs in one transaction holding an advisory lock, so binaries starting at
+/// the same time do not apply a migration twice.
+pub async fn migrate(client: &mut Client) -> Result<Vec<i32>, DbError> {
+    client
+        .batch_execute(
+            r#"
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version integer NOT NULL PRIMARY KEY,
+    name text NOT NULL,
+    applied_at timestamptz NOT NULL DEFAULT now()
+)"#,
+        )
+        .await?;
+
+    let tx = client.transaction().await?;
+    tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    let applied: BTreeSet<i32> = tx
+        .query("SELECT version FROM schema_migrations", &[])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    if let Some(&found) = applied.iter().next_back() {
+        if found > SCHEMA_VERSION {
+            return Err(DbError::SchemaTooNew {
+                found,
+                supported: SCHEMA_VERSION,
+            });
+        }
+    }
+
+    let mut done = Vec::new();
+    for m in pending(&applied) {
+        tx.batch_execute(m.sql).await?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+            &[&m.version, &m.name],
+        )
+        .await?;
+        done.push(m.version);
+    }
+    tx.commit().await?;
+    Ok(done)
+}
+
+fn pending(applied: &BTreeSet<i32>) -> impl Iterator<Item = &'static Migration> + '_ {
+    MIGRATIONS
+        .iter()
+        .filter(move |m| !applied.contains(&m.version))
+}
+
+/// Checks the version read with [`SCHEMA_VERSION_QUERY`] for binaries that only
+/// read the database and leave migrating to the others.
+pub fn check_schema_version(found: Option<i32>) -> Result<(), DbError> {
+    match found {
+        None => Err(DbError::SchemaMissing),
+        Some(found) if found < SCHEMA_VERSION => Err(DbError::SchemaOutdated {
+            found,
+            expected: SCHEMA_VERSION,
+        }),
+        Some(found) if found > SCHEMA_VERSION => Err(DbError::SchemaTooNew {
+            found,
+            supported: SCHEMA_VERSION,
+        }),
+        Some(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_increase() {
+        for pair in MIGRATIONS.windows(2) {
+            assert!(pair[0].version < pair[1].version);
+        }
+        assert_eq!(SCHEMA_VERSION, MIGRATIONS.last().unwrap().version);
+    }
+
+    #[test]
+    fn applies_only_pending() {
+        let applied: BTreeSet<i32> = vec![1].into_iter().collect();
+        let versions: Vec<i32> = pending(&applied).map(|m| m.version).collect();
+        assert_eq!(versions, vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn checks_version() {
+        assert!(check_schema_version(Some(SCHEMA_VERSION)).is_ok());
+        assert!(matches!(
+            check_schema_version(None),
+            Err(DbError::SchemaMissing)
+        ));
+        assert!(matches!(
+            check_schema_version(Some(SCHEMA_VERSION - 1)),
+            Err(DbError::SchemaOutdated { .. })
+        ));
+        assert!(matches!(
+            check_schema_version(Some(SCHEMA_VERSION + 1)),
+            Err(DbError::SchemaTooNew { .. })
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+use chrono::{Datelike, Duration, NaiveDate};
+use tokio_postgres::{Client, GenericClient, Statement};
+
+use crate::DbError;
+
+/// Version of the migration creating `wakatime_rollup`, filled from
+/// `wakatime_fact` right after it is applied.
+pub(crate) const ROLLUP_VERSION: i32 = 5;
+
+/// Clears one period, `$1` is the period name and `$2` its first day.
+pub(crate) const CLEAR_ROLLUP: &str =
+    "DELETE FROM wakatime_rollup WHERE period = $1 AND period_start = $2";
+
+/// Sums the facts of `$2..=$3` into the period `$1`.
+pub(crate) const INSERT_ROLLUP: &str = r#"
+INSERT INTO wakatime_rollup
+    (period, period_start, period_end, dimension, name, project, seconds)
+SELECT $1, $2, $3, dimension, name, project, sum(seconds)
+FROM wakatime_fact
+WHERE date >= $2 AND date <= $3
+GROUP BY dimension, name, project"#;
+
+/// Length of a row of `wakatime_rollup`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Period {
+    /// ISO week, Monday to Sunday
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    pub const ALL: [Period; 3] = [Period::Week, Period::Month, Period::Year];
+
+    /// Value of the `period` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::Year => "year",
+        }
+    }
+
+    /// First day of the period containing `date`.
+    pub fn start(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Period::Month => date.with_day(1).expect("every month has a first day"),
+            Period::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).expect("valid year"),
+        }
+    }
+
+    /// Last day of the period containing `date`.
+    pub fn end(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => self.start(date) + Duration::days(6),
+            Period::Month => {
+                let (y, m) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    m => (date.year(), m + 1),
+                };
+                NaiveDate::from_ymd_opt(y, m, 1).expect("valid month") - Duration::days(1)
+            }
+            Period::Year => NaiveDate::from_ymd_opt(date.year(), 12, 31).expect("valid year"),
+        }
+    }
+
+    /// Coarsest period whose whole periods make up exactly `from..=to`, so
+    /// that summing its rollups gives the same result as summing the days.
+    pub fn covering(from: NaiveDate, to: NaiveDate) -> Option<Period> {
+        if from > to {
+            return None;
+        }
+        Period::ALL
+            .iter()
+            .rev()
+            .copied()
+            .find(|p| p.start(from) == from && p.end(to) == to)
+    }
+}
+
+impl Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Every (period, first day) touched by `dates`.
+pub fn affected_periods(
+    dates: impl IntoIterator<Item = NaiveDate>,
+) -> BTreeSet<(Period, NaiveDate)> {
+    let mut periods = BTreeSet::new();
+    for date in dates {
+        for period in Period::ALL {
+            periods.insert((period, period.start(date)));
+        }
+    }
+    periods
+}
+
+/// Statements prepared from [`CLEAR_ROLLUP`] and [`INSERT_ROLLUP`].
+pub(crate) struct RollupStatements {
+    pub clear: Statement,
+    pub insert: Statement,
+}
+
+impl RollupStatements {
+    pub async fn prepare<C: GenericClient>(client: &C) -> Result<Self, tokio_postgres::Error> {
+        Ok(RollupStatements {
+            clear: client.prepare(CLEAR_ROLLUP).await?,
+            insert: client.prepare(INSERT_ROLLUP).await?,
+        })
+    }
+
+    /// Recomputes `periods` from `wakatime_fact`.
+    pub async fn refresh<C: GenericClient>(
+        &self,
+        client: &C,
+        periods: &BTreeSet<(Period, NaiveDate)>,
+    ) -> Result<(), tokio_postgres::Error> {
+        for &(period, start) in periods {
+            let name = period.as_str();
+            let end = period.end(start);
+            client.execute(&self.clear, &[&name, &start]).await?;
+            client.execute(&self.insert, &[&name, &start, &end]).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Recomputes every rollup of the stored days, returning the number of periods.
+pub async fn rebuild_rollups(client: &mut Client) -> Result<usize, DbError> {
+    let tx = client.transaction().await?;
+    tx.batch_execute("DELETE FROM wakatime_rollup").await?;
+    let dates = tx
+        .query("SELECT DISTINCT date FROM wakatime_fact", &[])
+        .await?
+        .iter()
+        .map(|row| row.get::<_, NaiveDate>(0))
+        .collect::<Vec<_>>();
+    let periods = affected_periods(dates);
+    RollupStatements::prepare(&tx)
+        .await?
+        .refresh(&tx, &periods)
+        .await?;
+    tx.commit().await?;
+    Ok(periods.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn finds_period_bounds() {
+        // 2021-02-24 is a Wednesday
+        let day = ymd(2021, 2, 24);
+        assert_eq!(Period::Week.start(day), ymd(2021, 2, 22));
+        assert_eq!(Period::Week.end(day), ymd(2021, 2, 28));
+        assert_eq!(Period::Month.end(day), ymd(2021, 2, 28));
+        assert_eq!(Period::Month.end(ymd(2020, 12, 5)), ymd(2020, 12, 31));
+        assert_eq!(Period::Year.start(day), ymd(2021, 1, 1));
+    }
+
+    #[test]
+    fn picks_coarsest_covering_period() {
+        let covering = |from, to| Period::covering(from, to);
+        assert_eq!(
+            covering(ymd(2019, 1, 1), ymd(2021, 12, 31)),
+            Some(Period::Year)
+        );
+        assert_eq!(
+            covering(ymd(2019, 3, 1), ymd(2021, 12, 31)),
+            Some(Period::Month)
+        );
+        assert_eq!(
+            covering(ymd(2021, 2, 22), ymd(2021, 3, 7)),
+            Some(Period::Week)
+        );
+        assert_eq!(covering(ymd(2021, 2, 22), ymd(2021, 3, 6)), None);
+        assert_eq!(covering(ymd(2021, 3, 1), ymd(2021, 2, 1)), None);
+    }
+
+    #[test]
+    fn collects_affected_periods() {
+        let periods = affected_periods(vec![ymd(2021, 2, 27), ymd(2021, 2, 28)]);
+        assert_eq!(periods.len(), 3);
+        let periods = affected_periods(vec![ymd(2021, 2, 28), ymd(2021, 3, 1)]);
+        assert_eq!(periods.len(), 5);
+    }
+}
@@ -0,0 +1,438 @@
+//! The same schema in a single SQLite file, for installs without a database
+//! server. JSON columns are stored as text and dates as `YYYY-MM-DD`.
+
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+use chrono::NaiveDate;
+use rusqlite::{params, Connection, ErrorCode, OpenFlags};
+use serde_json::Value;
+
+use crate::fact::facts;
+use crate::migrate::Migration;
+use crate::rollup::{affected_periods, Period};
+use crate::{DbError, SummaryRow, UpsertReport};
+
+/// Every migration in the order it is applied, with the same versions as
+/// [`crate::migrate::MIGRATIONS`].
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "wakatime_summary",
+        sql: include_str!("../migrations/sqlite/0001_wakatime_summary.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "summary_data",
+        sql: include_str!("../migrations/sqlite/0002_summary_data.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "all_dimensions",
+        sql: include_str!("../migrations/sqlite/0003_all_dimensions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "fact",
+        sql: include_str!("../migrations/sqlite/0004_fact.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "rollup",
+        sql: include_str!("../migrations/sqlite/0005_rollup.sql"),
+    },
+];
+
+/// Opens the database file at `path`, creating it when missing.
+pub fn open(path: impl AsRef<Path>) -> Result<Connection, DbError> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch("PRAGMA foreign_keys = ON")?;
+    Ok(conn)
+}
+
+/// Opens the database file at `path` read-only, `None` when it does not exist
+/// (an in-memory database never does).
+pub fn open_read_only(path: impl AsRef<Path>) -> Result<Option<Connection>, DbError> {
+    let path = path.as_ref();
+    if path == Path::new(":memory:") || !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )?))
+}
+
+/// Brings the database up to [`crate::SCHEMA_VERSION`], returning the versions
+/// applied. The write lock taken up front keeps binaries starting at the same
+/// time from applying a migration twice.
+pub fn migrate(conn: &mut Connection) -> Result<Vec<i32>, DbError> {
+    conn.execute_batch(
+        r#"
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+)"#,
+    )?;
+
+    let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+    let applied: BTreeSet<i32> = tx
+        .prepare("SELECT version FROM schema_migrations")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    if let Some(&found) = applied.iter().next_back() {
+        if found > crate::SCHEMA_VERSION {
+            return Err(DbError::SchemaTooNew {
+                found,
+                supported: crate::SCHEMA_VERSION,
+            });
+        }
+    }
+
+    let mut done = Vec::new();
+    for m in MIGRATIONS.iter().filter(|m| !applied.contains(&m.version)) {
+        tx.execute_batch(m.sql)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+            params![m.version, m.name],
+        )?;
+        done.push(m.version);
+    }
+    tx.commit()?;
+    Ok(done)
+}
+
+/// Same as [`crate::summary::upsert_rows`]. A row breaking a constraint is rolled back
+/// to its savepoint and reported, any other error aborts.
+pub fn upsert_rows<R: Borrow<SummaryRow>>(
+    conn: &mut Connection,
+    rows: &[R],
+    batch_size: usize,
+) -> Result<UpsertReport, DbError> {
+    let mut report = UpsertReport::default();
+    for batch in rows.chunks(batch_size.max(1)) {
+        let mut tx = conn.transaction()?;
+        let mut stored = Vec::with_capacity(batch.len());
+        for row in batch {
+            let row = row.borrow();
+            let mut savepoint = tx.savepoint()?;
+            match write_day(&savepoint, row) {
+                Ok(inserted) => {
+                    savepoint.commit()?;
+                    stored.push(row.date);
+                    if inserted {
+                        report.inserted += 1;
+                    } else {
+                        report.updated += 1;
+                    }
+                }
+                Err(rusqlite::Error::SqliteFailure(e, message))
+                    if e.code == ErrorCode::ConstraintViolation =>
+                {
+                    savepoint.rollback()?;
+                    report
+                        .failed
+                        .push((row.date, message.unwrap_or_else(|| e.to_string())));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        refresh_rollups(&tx, &affected_periods(stored))?;
+        tx.commit()?;
+    }
+    Ok(report)
+}
+
+/// Upserts one day and replaces its projects and facts, returning whether the
+/// day is new.
+fn write_day(conn: &Connection, row: &SummaryRow) -> Result<bool, rusqlite::Error> {
+    let exists: bool = conn
+        .prepare_cached("SELECT EXISTS (SELECT 1 FROM wakatime_summary WHERE date = ?1)")?
+        .query_row([&row.date], |res| res.get(0))?;
+    conn.prepare_cached(
+        r#"
+INSERT INTO wakatime_summary
+    (date, categories, editors, langs, machine, operating_systems, projects,
+     depends, range, grand_total_sec, data)
+VALUES
+    (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+ON CONFLICT (date) DO UPDATE SET
+    categories = excluded.categories,
+    editors = excluded.editors,
+    langs = excluded.langs,
+    machine = excluded.machine,
+    operating_systems = excluded.operating_systems,
+    projects = excluded.projects,
+    depends = excluded.depends,
+    range = excluded.range,
+    grand_total_sec = excluded.grand_total_sec,
+    data = excluded.data"#,
+    )?
+    .execute(params![
+        row.date,
+        row.categories,
+        row.editors,
+        row.langs,
+        row.machines,
+        row.operating_systems,
+        row.projects,
+        row.depends,
+        row.range,
+        row.grand_total_sec.as_f64(),
+        row.data,
+    ])?;
+
+    conn.prepare_cached("DELETE FROM wakatime_project_summary WHERE date = ?1")?
+        .execute([&row.date])?;
+    let mut insert = conn.prepare_cached(
+        r#"
+INSERT INTO wakatime_project_summary
+    (date, project, branches, entities, categories, editors, langs, machine,
+     depends, operating_systems, grand_total_sec)
+VALUES
+    (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"#,
+    )?;
+    for proj in &row.project_rows {
+        insert.execute(params![
+            row.date,
+            proj.project,
+            proj.branches,
+            proj.entities,
+            proj.categories,
+            proj.editors,
+            proj.langs,
+            proj.machines,
+            proj.depends,
+            proj.operating_systems,
+            proj.grand_total_sec.as_f64(),
+        ])?;
+    }
+    replace_facts(conn, row)?;
+    Ok(!exists)
+}
+
+fn replace_facts(conn: &Connection, row: &SummaryRow) -> Result<(), rusqlite::Error> {
+    conn.prepare_cached("DELETE FROM wakatime_fact WHERE date = ?1")?
+        .execute([&row.date])?;
+    let mut insert = conn.prepare_cached(
+        "INSERT INTO wakatime_fact (date, dimension, name, project, seconds) VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for fact in facts(row) {
+        insert.execute(params![
+            row.date,
+            fact.dimension,
+            fact.name,
+            fact.project,
+            fact.seconds.as_f64()
+        ])?;
+    }
+    Ok(())
+}
+
+fn refresh_rollups(
+    conn: &Connection,
+    periods: &BTreeSet<(Period, NaiveDate)>,
+) -> Result<(), rusqlite::Error> {
+    let mut clear =
+        conn.prepare_cached("DELETE FROM wakatime_rollup WHERE period = ?1 AND period_start = ?2")?;
+    let mut insert = conn.prepare_cached(
+        r#"
+INSERT INTO wakatime_rollup
+    (period, period_start, period_end, dimension, name, project, seconds)
+SELECT ?1, ?2, ?3, dimension, name, project, sum(seconds)
+FROM wakatime_fact
+WHERE date >= ?2 AND date <= ?3
+GROUP BY dimension, name, project"#,
+    )?;
+    for &(period, start) in periods {
+        clear.execute(params![period.as_str(), start])?;
+        insert.execute(params![period.as_str(), start, period.end(start)])?;
+    }
+    Ok(())
+}
+
+/// Same as [`crate::fact::rebuild_facts`].
+pub fn rebuild_facts(conn: &mut Connection) -> Result<usize, DbError> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM wakatime_fact", [])?;
+    let mut days = 0;
+    {
+        let mut select = tx.prepare(
+            "SELECT date, data FROM wakatime_summary WHERE data IS NOT NULL ORDER BY date",
+        )?;
+        let mut stored = select.query([])?;
+        while let Some(res) = stored.next()? {
+            let row = SummaryRow::from_value(res.get(0)?, res.get(1)?);
+            replace_facts(&tx, &row)?;
+            days += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(days)
+}
+
+/// Same as [`crate::rollup::rebuild_rollups`].
+pub fn rebuild_rollups(conn: &mut Connection) -> Result<usize, DbError> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM wakatime_rollup", [])?;
+    let dates: HashSet<NaiveDate> = tx
+        .prepare("SELECT DISTINCT date FROM wakatime_fact")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    let periods = affected_periods(dates);
+    refresh_rollups(&tx, &periods)?;
+    tx.commit()?;
+    Ok(periods.len())
+}
+
+/// Same as [`crate::summary::stored_dates`].
+pub fn stored_dates(conn: &Connection, from: NaiveDate) -> Result<BTreeSet<NaiveDate>, DbError> {
+    let dates = conn
+        .prepare_cached("SELECT date FROM wakatime_summary WHERE date >= ?1")?
+        .query_map([from], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(dates)
+}
+
+/// Same as [`crate::summary::stored_rows`].
+pub fn stored_rows(
+    conn: &Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<SummaryRow>, DbError> {
+    let rows = conn
+        .prepare_cached(
+            r#"
+SELECT date, data FROM wakatime_summary
+WHERE date >= ?1 AND date <= ?2 AND data IS NOT NULL
+ORDER BY date"#,
+        )?
+        .query_map([from, to], |row| {
+            Ok(SummaryRow::from_value(row.get(0)?, row.get::<_, Value>(1)?))
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comlib::fixtures;
+    use serde_json::json;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn row(date: NaiveDate, seconds: f64) -> SummaryRow {
+        let mut day = fixtures::day(&date.to_string(), seconds);
+        day["editors"] = json!([fixtures::detail("vim", seconds)]);
+        day["projects"] = json!([fixtures::detail("crate", seconds)]);
+        let mut project_day = fixtures::day(&date.to_string(), seconds);
+        project_day["branches"] = json!([fixtures::detail("main", seconds)]);
+        let mut doc = fixtures::summaries_all(vec![day]);
+        doc["projects"] = json!({ "crate": { "data": [project_day], "start": "", "end": "" } });
+        SummaryRow::from_value(date, doc)
+    }
+
+    fn migrated() -> Connection {
+        let mut conn = open(":memory:").unwrap();
+        assert_eq!(migrate(&mut conn).unwrap().len(), MIGRATIONS.len());
+        assert!(migrate(&mut conn).unwrap().is_empty());
+        conn
+    }
+
+    #[test]
+    fn versions_match_postgres() {
+        let versions = |migrations: &[Migration]| -> Vec<i32> {
+            migrations.iter().map(|m| m.version).collect()
+        };
+        assert_eq!(versions(MIGRATIONS), versions(crate::migrate::MIGRATIONS));
+    }
+
+    #[test]
+    fn upserts_days_with_facts_and_rollups() {
+        let mut conn = migrated();
+        let days = [row(ymd(2021, 2, 27), 60.0), row(ymd(2021, 2, 28), 120.0)];
+        let report = upsert_rows(&mut conn, &days, 1).unwrap();
+        assert_eq!((report.inserted, report.updated), (2, 0));
+        let report = upsert_rows(&mut conn, &[row(ymd(2021, 2, 28), 30.0)], 10).unwrap();
+        assert_eq!((report.inserted, report.updated), (0, 1));
+
+        let week: f64 = conn
+            .query_row(
+                "SELECT seconds FROM wakatime_rollup WHERE period = 'week' AND dimension = 'editors'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(week, 90.0);
+        let branches: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM wakatime_fact WHERE dimension = 'branches' AND project = 'crate'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(branches, 2);
+
+        let stored = stored_rows(&conn, ymd(2021, 2, 28), ymd(2021, 2, 28)).unwrap();
+        assert_eq!(stored, vec![row(ymd(2021, 2, 28), 30.0)]);
+        assert_eq!(stored_dates(&conn, ymd(2021, 2, 1)).unwrap().len(), 2);
+        assert_eq!(rebuild_facts(&mut conn).unwrap(), 2);
+        assert_eq!(rebuild_rollups(&mut conn).unwrap(), 3);
+    }
+
+    #[test]
+    fn opens_only_existing_files_read_only() {
+        let path = std::env::temp_dir().join(format!("comdb-ro-{}.db", std::process::id()));
+        assert!(open_read_only(":memory:").unwrap().is_none());
+        assert!(open_read_only(&path).unwrap().is_none());
+        assert!(!path.exists());
+
+        migrate(&mut open(&path).unwrap()).unwrap();
+        let conn = open_read_only(&path).unwrap().unwrap();
+        assert!(stored_dates(&conn, ymd(2021, 1, 1)).unwrap().is_empty());
+        assert!(conn.execute("DELETE FROM wakatime_summary", []).is_err());
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn migrates_files_of_the_old_sqlite_sink() {
+        let mut conn = open(":memory:").unwrap();
+        conn.execute_batch(
+            r#"
+CREATE TABLE wakatime_summary (
+    date TEXT PRIMARY KEY, editors TEXT NOT NULL, langs TEXT NOT NULL,
+    machine TEXT NOT NULL, projects TEXT NOT NULL, depends TEXT NOT NULL,
+    grand_total_sec REAL NOT NULL, data TEXT
+);
+INSERT INTO wakatime_summary VALUES
+    ('2021-02-22', '[]', '[]', '[]', '[]', '[]', 60,
+     '{"summaries":{"data":[{"categories":[{"name":"Coding","total_seconds":60}]}]},"projects":{"crate":{"data":[{"entities":[]}]}}}'),
+    ('2021-02-23', '[{"name":"vim","total_seconds":5}]', '[]', '[]', '[]', '[]', 5, NULL);
+"#,
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+        let categories: String = conn
+            .query_row(
+                "SELECT categories FROM wakatime_summary WHERE date = '2021-02-22'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert!(categories.contains("Coding"));
+        let projects: i64 = conn
+            .query_row("SELECT count(*) FROM wakatime_project_summary", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(projects, 1);
+        let rebuilt = stored_rows(&conn, ymd(2021, 2, 23), ymd(2021, 2, 23)).unwrap();
+        assert_eq!(rebuilt[0].editors[0]["name"], "vim");
+    }
+}
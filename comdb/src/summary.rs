@@ -0,0 +1,479 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+use chrono::NaiveDate;
+use comlib::{Seconds, SummariesAll};
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use serde_json::{json, Value};
+use tokio_postgres::{Client, Statement, Transaction};
+
+use crate::rollup::{affected_periods, RollupStatements};
+use crate::{fact, DbError};
+
+/// One day of the `wakatime_summary` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryRow {
+    pub date: NaiveDate,
+    pub categories: Value,
+    pub editors: Value,
+    pub langs: Value,
+    pub machines: Value,
+    pub operating_systems: Value,
+    pub projects: Value,
+    pub depends: Value,
+    pub range: Value,
+    pub grand_total_sec: Seconds,
+    /// The whole single-day `SummariesAll` document
+    pub data: Value,
+    /// Rows of `wakatime_project_summary`, one per project of the day
+    pub project_rows: Vec<ProjectRow>,
+}
+
+/// One project of a day in the `wakatime_project_summary` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectRow {
+    pub project: String,
+    pub branches: Value,
+    /// Files worked on
+    pub entities: Value,
+    pub categories: Value,
+    pub editors: Value,
+    pub langs: Value,
+    pub machines: Value,
+    pub depends: Value,
+    pub operating_systems: Value,
+    pub grand_total_sec: Seconds,
+}
+
+/// Value of `key` in one `SummariesData`, an empty array when missing.
+fn dimension(day: &Value, key: &str) -> Value {
+    day.get(key).cloned().unwrap_or_else(|| json!([]))
+}
+
+/// Operating systems of one `SummariesData`, named `operating_system` in
+/// documents written before comlib kept the API field names.
+fn operating_systems(day: &Value) -> Value {
+    match day.get("operating_systems") {
+        Some(value) => value.clone(),
+        None => dimension(day, "operating_system"),
+    }
+}
+
+fn total_seconds(day: &Value) -> Seconds {
+    Seconds(day["grand_total"]["total_seconds"].as_f64().unwrap_or(0.0))
+}
+
+/// `seconds` for a `NUMERIC` column of PostgreSQL.
+pub(crate) fn numeric(seconds: Seconds) -> Decimal {
+    Decimal::from_f64(seconds.as_f64()).unwrap_or_default()
+}
+
+impl SummaryRow {
+    /// Row of `date` taken from a single-day `SummariesAll` document, missing
+    /// dimensions are stored as empty arrays.
+    pub fn from_value(date: NaiveDate, data: Value) -> SummaryRow {
+        let day = &data["summaries"]["data"][0];
+        let project_rows = match data["projects"].as_object() {
+            Some(projects) => projects
+                .iter()
+                .filter(|(_, proj)| proj["data"][0].is_object())
+                .map(|(name, proj)| ProjectRow::from_day(name, &proj["data"][0]))
+                .collect(),
+            None => Vec::new(),
+        };
+        SummaryRow {
+            date,
+            categories: dimension(day, "categories"),
+            editors: dimension(day, "editors"),
+            langs: dimension(day, "languages"),
+            machines: dimension(day, "machines"),
+            operating_systems: operating_systems(day),
+            projects: dimension(day, "projects"),
+            depends: dimension(day, "dependencies"),
+            range: day.get("range").cloned().unwrap_or_else(|| json!({})),
+            grand_total_sec: total_seconds(day),
+            data,
+            project_rows,
+        }
+    }
+
+    /// One row per day of `summary`, which may cover several days.
+    pub fn from_summaries(summary: &SummariesAll) -> Result<Vec<SummaryRow>, DbError> {
+        let mut rows = Vec::new();
+        for (date, day) in summary.clone().split_by_day()? {
+            rows.push(SummaryRow::from_value(date, serde_json::to_value(&day)?));
+        }
+        Ok(rows)
+    }
+
+    /// Column of `name`, one of [`comlib::SummariesData::DIMENSIONS`].
+    /// `None` for the dimensions only projects have.
+    pub fn dimension(&self, name: &str) -> Option<&Value> {
+        match name {
+            "categories" => Some(&self.categories),
+            "dependencies" => Some(&self.depends),
+            "editors" => Some(&self.editors),
+            "languages" => Some(&self.langs),
+            "machines" => Some(&self.machines),
+            "operating_systems" => Some(&self.operating_systems),
+            "projects" => Some(&self.projects),
+            _ => None,
+        }
+    }
+}
+
+impl ProjectRow {
+    /// Row of `project` taken from its `SummariesData` of the day.
+    pub fn from_day(project: &str, day: &Value) -> ProjectRow {
+        ProjectRow {
+            project: project.to_string(),
+            branches: dimension(day, "branches"),
+            entities: dimension(day, "entities"),
+            categories: dimension(day, "categories"),
+            editors: dimension(day, "editors"),
+            langs: dimension(day, "languages"),
+            machines: dimension(day, "machines"),
+            depends: dimension(day, "dependencies"),
+            operating_systems: operating_systems(day),
+            grand_total_sec: total_seconds(day),
+        }
+    }
+
+    /// Column of `name`, one of [`comlib::SummariesData::DIMENSIONS`].
+    /// `None` for `projects`.
+    pub fn dimension(&self, name: &str) -> Option<&Value> {
+        match name {
+            "branches" => Some(&self.branches),
+            "categories" => Some(&self.categories),
+            "dependencies" => Some(&self.depends),
+            "editors" => Some(&self.editors),
+            "entities" => Some(&self.entities),
+            "languages" => Some(&self.langs),
+            "machines" => Some(&self.machines),
+            "operating_systems" => Some(&self.operating_systems),
+            _ => None,
+        }
+    }
+}
+
+/// Days per transaction used by [`upsert_rows`] when no batch size is given.
+pub const DEFAULT_BATCH_SIZE: usize = 500;
+
+/// Outcome of [`upsert_rows`].
+#[derive(Debug, Default, PartialEq)]
+pub struct UpsertReport {
+    pub inserted: usize,
+    pub updated: usize,
+    /// Days the database rejected, with the reason
+    pub failed: Vec<(NaiveDate, String)>,
+}
+
+impl Display for UpsertReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{} inserted, {} updated, {} failed",
+            self.inserted,
+            self.updated,
+            self.failed.len()
+        )
+    }
+}
+
+/// Inserts `rows`, replacing the days that are already stored.
+///
+/// Rows are written `batch_size` at a time, each batch in one transaction
+/// with one prepared statement. A row the database rejects is rolled back to
+/// its savepoint and reported in [`UpsertReport::failed`] without losing the
+/// rest of the batch. Losing the connection aborts with an error; batches
+/// committed before that stay stored.
+///
+/// The facts of each day and the rollups of the weeks, months and years
+/// containing the stored days are refreshed in the same transaction.
+pub async fn upsert_rows(
+    client: &mut Client,
+    rows: &[SummaryRow],
+    batch_size: usize,
+) -> Result<UpsertReport, DbError> {
+    let stmts = Statements {
+        day: client
+            .prepare(
+                r#"
+INSERT INTO wakatime_summary AS s
+    (date, categories, editors, langs, machine, operating_systems, projects,
+     depends, range, grand_total_sec, data)
+VALUES
+    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+ON CONFLICT (date) DO UPDATE SET
+    categories = EXCLUDED.categories,
+    editors = EXCLUDED.editors,
+    langs = EXCLUDED.langs,
+    machine = EXCLUDED.machine,
+    operating_systems = EXCLUDED.operating_systems,
+    projects = EXCLUDED.projects,
+    depends = EXCLUDED.depends,
+    range = EXCLUDED.range,
+    grand_total_sec = EXCLUDED.grand_total_sec,
+    data = EXCLUDED.data
+RETURNING (xmax = 0) AS inserted"#,
+            )
+            .await?,
+        clear_projects: client
+            .prepare("DELETE FROM wakatime_project_summary WHERE date = $1")
+            .await?,
+        project: client
+            .prepare(
+                r#"
+INSERT INTO wakatime_project_summary
+    (date, project, branches, entities, categories, editors, langs, machine,
+     depends, operating_systems, grand_total_sec)
+VALUES
+    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
+            )
+            .await?,
+        clear_facts: client.prepare(fact::CLEAR_FACTS).await?,
+        facts: client.prepare(fact::INSERT_FACTS).await?,
+    };
+
+    let rollups = RollupStatements::prepare(client).await?;
+
+    let mut report = UpsertReport::default();
+    for batch in rows.chunks(batch_size.max(1)) {
+        let mut tx = client.transaction().await?;
+        let mut stored = Vec::with_capacity(batch.len());
+        for row in batch {
+            let savepoint = tx.transaction().await?;
+            match write_day(&savepoint, &stmts, row).await {
+                Ok(inserted) => {
+                    savepoint.commit().await?;
+                    stored.push(row.date);
+                    if inserted {
+                        report.inserted += 1;
+                    } else {
+                        report.updated += 1;
+                    }
+                }
+                // rejected by the server, e.g. a constraint: skip only this day
+                Err(e) if e.as_db_error().is_some() => {
+                    let reason = e
+                        .as_db_error()
+                        .map(|db| db.message().to_string())
+                        .unwrap_or_default();
+                    savepoint.rollback().await?;
+                    report.failed.push((row.date, reason));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        rollups.refresh(&tx, &affected_periods(stored)).await?;
+        tx.commit().await?;
+    }
+    Ok(report)
+}
+
+/// Dates stored from `from` on.
+pub async fn stored_dates(
+    client: &Client,
+    from: NaiveDate,
+) -> Result<BTreeSet<NaiveDate>, DbError> {
+    let rows = client
+        .query(
+            "SELECT date FROM wakatime_summary WHERE date >= $1",
+            &[&from],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Days stored in `from..=to`, read back from their `data` document.
+pub async fn stored_rows(
+    client: &Client,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<SummaryRow>, DbError> {
+    let rows = client
+        .query(
+            r#"
+SELECT date, data FROM wakatime_summary
+WHERE date >= $1 AND date <= $2 AND data IS NOT NULL
+ORDER BY date"#,
+            &[&from, &to],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| SummaryRow::from_value(row.get(0), row.get(1)))
+        .collect())
+}
+
+struct Statements {
+    day: Statement,
+    clear_projects: Statement,
+    project: Statement,
+    clear_facts: Statement,
+    facts: Statement,
+}
+
+/// Upserts one day and replaces its projects and facts, returning whether the
+/// day is new.
+async fn write_day(
+    tx: &Transaction<'_>,
+    stmts: &Statements,
+    row: &SummaryRow,
+) -> Result<bool, tokio_postgres::Error> {
+    let res = tx
+        .query_one(
+            &stmts.day,
+            &[
+                &row.date,
+                &row.categories,
+                &row.editors,
+                &row.langs,
+                &row.machines,
+                &row.operating_systems,
+                &row.projects,
+                &row.depends,
+                &row.range,
+                &numeric(row.grand_total_sec),
+                &row.data,
+            ],
+        )
+        .await?;
+    tx.execute(&stmts.clear_projects, &[&row.date]).await?;
+    for proj in &row.project_rows {
+        tx.execute(
+            &stmts.project,
+            &[
+                &row.date,
+                &proj.project,
+                &proj.branches,
+                &proj.entities,
+                &proj.categories,
+                &proj.editors,
+                &proj.langs,
+                &proj.machines,
+                &proj.depends,
+                &proj.operating_systems,
+                &numeric(proj.grand_total_sec),
+            ],
+        )
+        .await?;
+    }
+    let facts = fact::facts(row);
+    fact::replace_facts(tx, &stmts.clear_facts, &stmts.facts, row.date, &facts).await?;
+    Ok(res.get("inserted"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comlib::fixtures;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn day(date: &str) -> Value {
+        let mut day = fixtures::day(date, 3900.0);
+        day["editors"] = json!([fixtures::detail("vim", 3900.0)]);
+        day["languages"] = json!([fixtures::detail("Rust", 3900.0)]);
+        day["projects"] = json!([fixtures::detail("crate", 3900.0)]);
+        day
+    }
+
+    #[test]
+    fn takes_columns_from_document() {
+        let mut project_day = day("2021-02-22");
+        project_day["branches"] = json!([{ "name": "main", "total_seconds": 600.0 }]);
+        let doc = json!({
+            "summaries": { "data": [day("2021-02-22")] },
+            "projects": { "crate": { "data": [project_day] }, "idle": { "data": [] } }
+        });
+        let row = SummaryRow::from_value(ymd(2021, 2, 22), doc.clone());
+        assert_eq!(row.langs[0]["name"], "Rust");
+        assert_eq!(row.machines, json!([]));
+        assert_eq!(row.operating_systems, json!([]));
+        assert_eq!(row.range["date"], "2021-02-22");
+        assert_eq!(row.project_rows.len(), 1);
+        assert_eq!(row.project_rows[0].project, "crate");
+        assert_eq!(row.project_rows[0].branches[0]["name"], "main");
+        assert_eq!(row.project_rows[0].entities, json!([]));
+        assert_eq!(row.grand_total_sec, Seconds(3900.0));
+        assert_eq!(row.data, doc);
+
+        let empty = SummaryRow::from_value(ymd(2021, 2, 22), json!({}));
+        assert_eq!(empty.editors, json!([]));
+        assert_eq!(empty.grand_total_sec, Seconds(0.0));
+        assert!(empty.project_rows.is_empty());
+    }
+
+    /// Client of the scratch PostgreSQL database in `COMDB_TEST_DB_URL`,
+    /// migrated in `schema` created afresh; `None` skips the test.
+    async fn test_client(schema: &str) -> Option<Client> {
+        let db_url = std::env::var("COMDB_TEST_DB_URL").ok()?;
+        let (mut client, connection) = tokio_postgres::connect(&db_url, tokio_postgres::NoTls)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        client
+            .batch_execute(&format!(
+                "DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0}; SET search_path TO {0}",
+                schema
+            ))
+            .await
+            .unwrap();
+        crate::migrate::migrate(&mut client).await.unwrap();
+        Some(client)
+    }
+
+    #[tokio::test]
+    async fn counts_inserted_and_updated_days() {
+        let mut client = match test_client("comdb_test_upsert").await {
+            Some(client) => client,
+            None => return,
+        };
+        let summary: SummariesAll = serde_json::from_value(json!({
+            "summaries": { "data": [day("2021-02-22"), day("2021-02-23")], "start": "", "end": "" },
+            "projects": {}
+        }))
+        .unwrap();
+        let rows = SummaryRow::from_summaries(&summary).unwrap();
+
+        let report = upsert_rows(&mut client, &rows, 1).await.unwrap();
+        assert_eq!((report.inserted, report.updated), (2, 0));
+        let report = upsert_rows(&mut client, &rows[1..], 10).await.unwrap();
+        assert_eq!((report.inserted, report.updated), (0, 1));
+        assert!(report.failed.is_empty());
+
+        client
+            .batch_execute("DROP SCHEMA comdb_test_upsert CASCADE")
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn reports_counts() {
+        let report = UpsertReport {
+            inserted: 3,
+            updated: 1,
+            failed: vec![(ymd(2021, 2, 22), "rejected".into())],
+        };
+        assert_eq!(report.to_string(), "3 inserted, 1 updated, 1 failed");
+    }
+
+    #[test]
+    fn splits_ranges_into_days() {
+        let summary: SummariesAll = serde_json::from_value(json!({
+            "summaries": { "data": [day("2021-02-22"), day("2021-02-23")], "start": "", "end": "" },
+            "projects": {}
+        }))
+        .unwrap();
+        let rows = SummaryRow::from_summaries(&summary).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].date, ymd(2021, 2, 23));
+        assert_eq!(
+            rows[1].data["summaries"]["data"].as_array().unwrap().len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Totals of several days, merged per dimension and name.
+
+use std::collections::BTreeMap;
+
+use crate::{Seconds, SummariesAll, SummariesData};
+
+const NO_TOTALS: &Totals = &Totals {
+    seconds: BTreeMap::new(),
+};
+
+/// Seconds per name in one dimension.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Totals {
+    seconds: BTreeMap<String, Seconds>,
+}
+
+/// One name of a dimension with its share of the dimension total.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Share {
+    pub name: String,
+    pub seconds: Seconds,
+    /// Percent of [`Totals::total`], 0 to 100
+    pub percent: f64,
+}
+
+impl Totals {
+    pub fn add(&mut self, name: &str, seconds: Seconds) {
+        *self.seconds.entry(name.to_string()).or_default() += seconds;
+    }
+
+    pub fn merge(&mut self, other: &Totals) {
+        for (name, seconds) in &other.seconds {
+            self.add(name, *seconds);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Seconds> {
+        self.seconds.get(name).copied()
+    }
+
+    /// Every name with its seconds, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Seconds)> {
+        self.seconds
+            .iter()
+            .map(|(name, seconds)| (name.as_str(), *seconds))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seconds.is_empty()
+    }
+
+    /// Sum of every name. Not the time worked for `dependencies`, which
+    /// overlap each other.
+    pub fn total(&self) -> Seconds {
+        self.seconds.values().sum()
+    }
+
+    /// Every name, longest first and by name on ties.
+    pub fn ranked(&self) -> Vec<Share> {
+        let total = self.total().as_f64();
+        let mut shares: Vec<Share> = self
+            .seconds
+            .iter()
+            .map(|(name, seconds)| Share {
+                name: name.clone(),
+                seconds: *seconds,
+                percent: if total > 0.0 {
+                    seconds.as_f64() / total * 100.0
+                } else {
+                    0.0
+                },
+            })
+            .collect();
+        shares.sort_by(|a, b| {
+            b.seconds
+                .as_f64()
+                .total_cmp(&a.seconds.as_f64())
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        shares
+    }
+
+    /// The `n` longest names and `other` holding the rest, if any.
+    ///
+    /// A name equal to `other` (WakaTime reports an "Other" language) always
+    /// goes into the bucket so that it appears once.
+    pub fn top(&self, n: usize, other: &str) -> Vec<Share> {
+        let (mut top, rest): (Vec<Share>, Vec<Share>) = self
+            .ranked()
+            .into_iter()
+            .partition(|share| share.name != other);
+        let mut bucket: Vec<Share> = rest;
+        if top.len() > n {
+            bucket.extend(top.split_off(n));
+        }
+        if !bucket.is_empty() {
+            top.push(Share {
+                name: other.to_string(),
+                seconds: bucket.iter().map(|s| s.seconds).sum(),
+                percent: bucket.iter().map(|s| s.percent).sum(),
+            });
+        }
+        top
+    }
+}
+
+/// Totals of any number of days.
+///
+/// Days are added with [`Aggregate::add_day`] or, with their per-project
+/// breakdown, [`Aggregate::add_summaries`]; aggregates of other days are
+/// combined with [`Aggregate::merge`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Aggregate {
+    /// Days added
+    pub days: usize,
+    pub grand_total: Seconds,
+    dimensions: BTreeMap<&'static str, Totals>,
+    /// Aggregate of each project over its own days, with `branches` and
+    /// `entities`
+    pub projects: BTreeMap<String, Aggregate>,
+}
+
+impl Aggregate {
+    pub fn new() -> Aggregate {
+        Aggregate::default()
+    }
+
+    /// Aggregate of the days in `all`, projects included.
+    pub fn from_summaries<'a>(all: impl IntoIterator<Item = &'a SummariesAll>) -> Aggregate {
+        let mut aggregate = Aggregate::new();
+        for summaries in all {
+            aggregate.add_summaries(summaries);
+        }
+        aggregate
+    }
+
+    /// Aggregate of `days`, without the per-project breakdown.
+    pub fn from_days<'a>(days: impl IntoIterator<Item = &'a SummariesData>) -> Aggregate {
+        let mut aggregate = Aggregate::new();
+        for day in days {
+            aggregate.add_day(day);
+        }
+        aggregate
+    }
+
+    pub fn add_day(&mut self, day: &SummariesData) {
+        self.days += 1;
+        self.grand_total += day.grand_total.total_seconds;
+        for (dimension, entries) in day.dimensions() {
+            if entries.is_empty() {
+                continue;
+            }
+            let totals = self.dimensions.entry(dimension).or_default();
+            for entry in entries {
+                totals.add(&entry.name, entry.total_seconds);
+            }
+        }
+    }
+
+    /// Adds every day of `all` and the days of its projects.
+    pub fn add_summaries(&mut self, all: &SummariesAll) {
+        for day in &all.summaries.data {
+            self.add_day(day);
+        }
+        for (name, project) in &all.projects {
+            let aggregate = self.projects.entry(name.clone()).or_default();
+            for day in &project.data {
+                aggregate.add_day(day);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Aggregate) {
+        self.days += other.days;
+        self.grand_total += other.grand_total;
+        for (dimension, totals) in &other.dimensions {
+            self.dimensions.entry(dimension).or_default().merge(totals);
+        }
+        for (name, project) in &other.projects {
+            self.projects
+                .entry(name.clone())
+                .or_default()
+                .merge(project);
+        }
+    }
+
+    /// Totals of one of [`SummariesData::DIMENSIONS`], empty when no day had
+    /// it.
+    pub fn totals(&self, dimension: &str) -> &Totals {
+        self.dimensions.get(dimension).unwrap_or(NO_TOTALS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::detail;
+    use serde_json::json;
+
+    fn day(date: &str, langs: &[(&str, f64)], branches: Option<&[(&str, f64)]>) -> SummariesData {
+        let details = |entries: &[(&str, f64)]| {
+            entries
+                .iter()
+                .map(|(name, secs)| detail(name, *secs))
+                .collect::<Vec<_>>()
+        };
+        let total: f64 = langs.iter().map(|(_, secs)| secs).sum();
+        let mut day = json!({
+            "categories": [detail("Coding", total)], "dependencies": [], "editors": [],
+            "languages": details(langs), "machines": [], "projects": [detail("crate", total)],
+            "grand_total": detail("", total),
+            "range": { "date": date, "start": "", "end": "", "text": "" }
+        });
+        if let Some(branches) = branches {
+            day["branches"] = details(branches).into();
+        }
+        serde_json::from_value(day).unwrap()
+    }
+
+    #[test]
+    fn merges_days_and_projects() {
+        let all = SummariesAll {
+            schema_version: crate::SCHEMA_VERSION,
+            summaries: crate::Summaries {
+                data: vec![
+                    day("2021-02-22", &[("Rust", 3000.0), ("SQL", 600.0)], None),
+                    day("2021-02-23", &[("Rust", 1200.0)], None),
+                ],
+                start: "".into(),
+                end: "".into(),
+                extra: Default::default(),
+            },
+            projects: vec![(
+                "crate".to_string(),
+                crate::Summaries {
+                    data: vec![
+                        day("2021-02-22", &[("Rust", 3000.0)], Some(&[("main", 3000.0)])),
+                        day("2021-02-23", &[("Rust", 1200.0)], Some(&[("main", 1200.0)])),
+                    ],
+                    start: "".into(),
+                    end: "".into(),
+                    extra: Default::default(),
+                },
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let first = Aggregate::from_summaries(vec![&all]);
+        assert_eq!(first.days, 2);
+        assert_eq!(first.grand_total, Seconds(4800.0));
+        assert_eq!(first.totals("languages").get("Rust"), Some(Seconds(4200.0)));
+        assert_eq!(
+            first.projects["crate"].totals("branches").get("main"),
+            Some(Seconds(4200.0))
+        );
+        assert!(first.totals("branches").is_empty());
+
+        let mut twice = first.clone();
+        twice.merge(&first);
+        assert_eq!(twice.days, 4);
+        assert_eq!(
+            twice.totals("categories").get("Coding"),
+            Some(Seconds(9600.0))
+        );
+        let ranked = twice.totals("languages").ranked();
+        assert_eq!(ranked[0].name, "Rust");
+        assert_eq!(ranked[0].percent, 87.5);
+    }
+
+    #[test]
+    fn keeps_the_top_names_and_buckets_the_rest() {
+        let day = day(
+            "2021-02-22",
+            &[
+                ("Rust", 500.0),
+                ("Other", 100.0),
+                ("SQL", 200.0),
+                ("TOML", 150.0),
+                ("YAML", 50.0),
+            ],
+            None,
+        );
+        let top = Aggregate::from_days(vec![&day])
+            .totals("languages")
+            .top(2, "Other");
+        let names: Vec<(&str, f64, f64)> = top
+            .iter()
+            .map(|s| (s.name.as_str(), s.seconds.as_f64(), s.percent))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("Rust", 500.0, 50.0),
+                ("SQL", 200.0, 20.0),
+                ("Other", 300.0, 30.0)
+            ]
+        );
+
+        let all = Aggregate::from_days(vec![&day])
+            .totals("languages")
+            .top(10, "Other");
+        assert_eq!(all.len(), 5);
+        assert_eq!(all.last().unwrap().name, "Other");
+    }
+}
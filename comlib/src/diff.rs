@@ -0,0 +1,287 @@
+//! Differences between two aggregates, e.g. last week and this week or a day
+//! as first fetched and as WakaTime revised it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+
+use serde::Serialize;
+
+use crate::validate::DEFAULT_TOLERANCE;
+use crate::{Aggregate, Seconds, SummariesAll, SummariesData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// Only in the later aggregate
+    Added,
+    /// Only in the earlier aggregate
+    Removed,
+    /// In both, with seconds more than [`DEFAULT_TOLERANCE`] apart
+    Changed,
+}
+
+/// Seconds before and after with their difference.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Delta {
+    pub before: Seconds,
+    pub after: Seconds,
+    /// `after - before`
+    pub delta: Seconds,
+    /// `delta` in percent of `before`, `None` when `before` is 0
+    pub percent: Option<f64>,
+}
+
+/// A name of a dimension whose seconds differ.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    pub name: String,
+    pub kind: ChangeKind,
+    #[serde(flatten)]
+    pub delta: Delta,
+}
+
+/// What differs between two aggregates, dimension by dimension.
+///
+/// Names within [`DEFAULT_TOLERANCE`] on both sides are left out, so is a
+/// dimension without changes. Serialized as
+/// `{"grand_total": {...}, "dimensions": {"languages": [...], ...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diff {
+    pub grand_total: Delta,
+    /// Changes of each of [`SummariesData::DIMENSIONS`], largest difference
+    /// first
+    pub dimensions: BTreeMap<&'static str, Vec<Change>>,
+}
+
+impl Delta {
+    pub fn new(before: Seconds, after: Seconds) -> Delta {
+        let delta = after - before;
+        Delta {
+            before,
+            after,
+            delta,
+            percent: (before.as_f64() != 0.0).then(|| delta.as_f64() / before.as_f64() * 100.0),
+        }
+    }
+}
+
+impl Diff {
+    /// Compares the grand totals and dimensions of two aggregates. Their
+    /// [`Aggregate::projects`] are not compared; diff two of those with
+    /// another call.
+    pub fn new(before: &Aggregate, after: &Aggregate) -> Diff {
+        let mut dimensions = BTreeMap::new();
+        for dimension in SummariesData::DIMENSIONS {
+            let (old, new) = (before.totals(dimension), after.totals(dimension));
+            let names: BTreeSet<&str> = old.iter().chain(new.iter()).map(|(n, _)| n).collect();
+            let mut changes: Vec<Change> = names
+                .into_iter()
+                .filter_map(|name| {
+                    let kind = match (old.get(name), new.get(name)) {
+                        (None, _) => ChangeKind::Added,
+                        (_, None) => ChangeKind::Removed,
+                        (Some(a), Some(b)) if differ(a, b) => ChangeKind::Changed,
+                        _ => return None,
+                    };
+                    Some(Change {
+                        name: name.to_string(),
+                        kind,
+                        delta: Delta::new(
+                            old.get(name).unwrap_or_default(),
+                            new.get(name).unwrap_or_default(),
+                        ),
+                    })
+                })
+                .collect();
+            if changes.is_empty() {
+                continue;
+            }
+            changes.sort_by(|a, b| {
+                b.delta
+                    .delta
+                    .as_f64()
+                    .abs()
+                    .total_cmp(&a.delta.delta.as_f64().abs())
+                    .then_with(|| a.name.cmp(&b.name))
+            });
+            dimensions.insert(dimension, changes);
+        }
+        Diff {
+            grand_total: Delta::new(before.grand_total, after.grand_total),
+            dimensions,
+        }
+    }
+
+    /// Difference between the days of `before` and those of `after`.
+    pub fn of_days<'a>(
+        before: impl IntoIterator<Item = &'a SummariesData>,
+        after: impl IntoIterator<Item = &'a SummariesData>,
+    ) -> Diff {
+        Diff::new(&Aggregate::from_days(before), &Aggregate::from_days(after))
+    }
+
+    /// Difference between the main summaries of two responses, see
+    /// [`Diff::new`].
+    pub fn of_summaries(before: &SummariesAll, after: &SummariesAll) -> Diff {
+        Diff::of_days(&before.summaries.data, &after.summaries.data)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !differ(self.grand_total.before, self.grand_total.after) && self.dimensions.is_empty()
+    }
+}
+
+/// Whether `a` and `b` are further apart than WakaTime's rounding.
+fn differ(a: Seconds, b: Seconds) -> bool {
+    (b - a).as_f64().abs() > DEFAULT_TOLERANCE.as_f64()
+}
+
+/// `+H:MM` or `-H:MM`, or `+Ns` under a minute so that small revisions do
+/// not show as `+0:00`.
+fn signed_hm(seconds: Seconds) -> String {
+    let sign = if seconds.as_f64() < 0.0 { '-' } else { '+' };
+    let abs = seconds.as_f64().abs();
+    if abs > 0.0 && abs < 60.0 {
+        format!("{}{}s", sign, abs.floor())
+    } else {
+        format!("{}{}", sign, Seconds(abs).hm())
+    }
+}
+
+fn write_row(f: &mut fmt::Formatter, mark: char, name: &str, delta: &Delta) -> fmt::Result {
+    let percent = match delta.percent {
+        Some(percent) => format!("{:+.1}%", percent),
+        None => "-".to_string(),
+    };
+    writeln!(
+        f,
+        "{} {:<24} {:>8} {:>8} {:>8} {:>8}",
+        mark,
+        name,
+        delta.before.hm(),
+        delta.after.hm(),
+        signed_hm(delta.delta),
+        percent
+    )
+}
+
+/// One table per dimension; `+` marks added names, `-` removed ones and `~`
+/// changed ones.
+impl Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "  {:<24} {:>8} {:>8} {:>8} {:>8}",
+            "", "before", "after", "delta", "%"
+        )?;
+        write_row(f, ' ', "grand_total", &self.grand_total)?;
+        for (dimension, changes) in &self.dimensions {
+            writeln!(f, "[{}]", dimension)?;
+            for change in changes {
+                let mark = match change.kind {
+                    ChangeKind::Added => '+',
+                    ChangeKind::Removed => '-',
+                    ChangeKind::Changed => '~',
+                };
+                write_row(f, mark, &change.name, &change.delta)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::detail;
+    use serde_json::json;
+
+    fn day(langs: &[(&str, f64)]) -> SummariesData {
+        let total: f64 = langs.iter().map(|(_, secs)| secs).sum();
+        serde_json::from_value(json!({
+            "categories": [detail("Coding", total)], "dependencies": [], "editors": [],
+            "languages": langs.iter().map(|(n, s)| detail(n, *s)).collect::<Vec<_>>(),
+            "machines": [], "projects": [], "grand_total": detail("", total),
+            "range": { "date": "2021-02-22", "start": "", "end": "", "text": "" }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn reports_added_removed_and_changed_names() {
+        let before = day(&[("Rust", 3600.0), ("SQL", 600.0), ("TOML", 60.0)]);
+        let after = day(&[("Rust", 5400.0), ("SQL", 600.0), ("YAML", 300.0)]);
+        let diff = Diff::of_days(vec![&before], vec![&after]);
+
+        assert_eq!(
+            diff.grand_total,
+            Delta::new(Seconds(4260.0), Seconds(6300.0))
+        );
+        let langs: Vec<(&str, ChangeKind, f64, Option<f64>)> = diff.dimensions["languages"]
+            .iter()
+            .map(|c| {
+                (
+                    c.name.as_str(),
+                    c.kind,
+                    c.delta.delta.as_f64(),
+                    c.delta.percent,
+                )
+            })
+            .collect();
+        assert_eq!(
+            langs,
+            vec![
+                ("Rust", ChangeKind::Changed, 1800.0, Some(50.0)),
+                ("YAML", ChangeKind::Added, 300.0, None),
+                ("TOML", ChangeKind::Removed, -60.0, Some(-100.0)),
+            ]
+        );
+        assert_eq!(diff.dimensions.len(), 2);
+        assert!(Diff::of_days(vec![&before], vec![&before]).is_empty());
+
+        let rounded = day(&[("Rust", 3600.4), ("SQL", 600.0), ("TOML", 60.0)]);
+        assert!(Diff::of_days(vec![&before], vec![&rounded]).is_empty());
+    }
+
+    #[test]
+    fn shows_seconds_under_a_minute() {
+        assert_eq!(signed_hm(Seconds(-42.5)), "-42s");
+        assert_eq!(signed_hm(Seconds(42.0)), "+42s");
+        assert_eq!(signed_hm(Seconds(0.0)), "+0:00");
+        assert_eq!(signed_hm(Seconds(5400.0)), "+1:30");
+    }
+
+    #[test]
+    fn serializes_and_prints() {
+        let before = day(&[("Rust", 3600.0)]);
+        let after = day(&[("Rust", 1800.0)]);
+        let diff = Diff::of_days(vec![&before], vec![&after]);
+
+        let value = serde_json::to_value(&diff).unwrap();
+        assert_eq!(
+            value["dimensions"]["languages"][0],
+            json!({
+                "name": "Rust", "kind": "changed", "before": 3600.0,
+                "after": 1800.0, "delta": -1800.0, "percent": -50.0
+            })
+        );
+
+        let lines: Vec<Vec<String>> = diff
+            .to_string()
+            .lines()
+            .map(|l| l.split_whitespace().map(String::from).collect())
+            .collect();
+        let row = |cells: &[&str]| cells.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                row(&["before", "after", "delta", "%"]),
+                row(&["grand_total", "1:00", "0:30", "-0:30", "-50.0%"]),
+                row(&["[categories]"]),
+                row(&["~", "Coding", "1:00", "0:30", "-0:30", "-50.0%"]),
+                row(&["[languages]"]),
+                row(&["~", "Rust", "1:00", "0:30", "-0:30", "-50.0%"]),
+            ]
+        );
+    }
+}
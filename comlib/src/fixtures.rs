@@ -0,0 +1,32 @@
+//! JSON pieces of API responses for tests, here and in the other crates of
+//! the workspace through the `test-fixtures` feature.
+
+use serde_json::{json, Value};
+
+/// A `SummariesDetail` with only `name` and `total_seconds` set, `name`
+/// empty for a `grand_total`.
+pub fn detail(name: &str, secs: f64) -> Value {
+    json!({
+        "digital": "", "hours": 0, "minutes": 0, "name": name,
+        "text": "", "total_seconds": secs
+    })
+}
+
+/// A `SummariesData` of `date` with a `grand_total` of `secs` and every
+/// dimension empty, tests fill in the ones they look at.
+pub fn day(date: &str, secs: f64) -> Value {
+    json!({
+        "categories": [], "dependencies": [], "editors": [], "languages": [],
+        "machines": [], "projects": [],
+        "grand_total": detail("", secs),
+        "range": { "date": date, "start": "", "end": "", "text": "" }
+    })
+}
+
+/// A `SummariesAll` of `days` without project summaries.
+pub fn summaries_all(days: Vec<Value>) -> Value {
+    json!({
+        "summaries": { "data": days, "start": "", "end": "" },
+        "projects": {}
+    })
+}
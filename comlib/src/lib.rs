@@ -1,5 +1,21 @@
-use std::collections::HashMap;
-use serde::{Serialize, Deserialize};
+use chrono::{DateTime, NaiveDate, ParseError};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+pub mod aggregate;
+pub mod diff;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
+pub mod seconds;
+pub mod token_store;
+pub mod validate;
+
+pub use aggregate::{Aggregate, Share, Totals};
+pub use diff::{Change, ChangeKind, Delta, Diff};
+pub use seconds::Seconds;
+pub use validate::{validate, Finding, FindingKind};
 
 /*
     waka time json schema
@@ -9,10 +25,10 @@ use serde::{Serialize, Deserialize};
         hours: i32
         minutes: i32
         name: Option<string>
-        percent: f32
-        seconds: i32
+        percent: Option<f64>
+        seconds: Option<i32>
         text: string
-        total_seconds: f32
+        total_seconds: <seconds>
         machine_name_id: Option<string>     // exists machines only
     }
 
@@ -24,7 +40,7 @@ use serde::{Serialize, Deserialize};
         editors: [ <summaries_detail> ]
         languages: [ <summaries_detail> ]
         machines: [ <summaries_detail> ]
-        operating_system: [ <summaries_detail> ]
+        operating_systems: [ <summaries_detail> ]
         projects: [ <summaries_detail> ]
         grand_total: <summaries_detail>
         range: {
@@ -52,84 +68,334 @@ use serde::{Serialize, Deserialize};
     datetime string := "YYYY'-'MM'-'DD'T'HH':'mi':'ss'Z'"    // e.g. 2021-02-22T14:59:59Z
 */
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Version written to the `schema_version` of saved `SummariesAll` files.
+///
+/// 1: files written before the marker existed, fields unknown to these types
+///    were dropped
+/// 2: every field returned by WakaTime is kept
+pub const SCHEMA_VERSION: u32 = 2;
+
+fn first_schema_version() -> u32 {
+    1
+}
+
+// Fields WakaTime leaves out are left out again when serializing, and fields
+// these types do not know are kept in `extra`, so that a saved file holds
+// what the API returned.
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct SummariesDetail {
     pub digital: String,
+    /// `hours`, `minutes` and `seconds` are the parts of `total_seconds` the
+    /// API shows, use [`Seconds`] for arithmetic
     pub hours: i32,
     pub minutes: i32,
-    #[serde(default)]
-    pub seconds: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seconds: Option<i32>,
+    /// empty for `grand_total`, written even then
     #[serde(default)]
     pub name: String,
-    #[serde(default)]
-    pub percent: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percent: Option<f64>,
     pub text: String,
-    pub total_seconds: f32,
-    pub machine_name_id: Option<String>
+    pub total_seconds: Seconds,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine_name_id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-impl Default for SummariesDetail {
-    fn default() -> Self {
-        SummariesDetail {
-             digital: "".into(),
-            hours: 0,
-            minutes: 0,
-            seconds: 0,
-            name: "".into(),
-            percent: 0.0,
-            text: "".into(),
-            total_seconds: 0.0,
-            machine_name_id: Some("".into()),
-        }
-    }
-}
-
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RangeData {
     pub date: String,
     pub start: String,
     pub end: String,
     pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub timezone: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl RangeData {
+    /// The day, `YYYY-MM-DD` in `timezone`.
+    pub fn parsed_date(&self) -> Result<NaiveDate, ParseError> {
+        NaiveDate::parse_from_str(&self.date, "%Y-%m-%d")
+    }
+
+    /// `timezone` of the user, `None` when missing or unknown.
+    pub fn tz(&self) -> Option<Tz> {
+        self.timezone.as_deref().and_then(|name| name.parse().ok())
+    }
+
+    /// Start of the range in [`RangeData::tz`], in UTC without one.
+    pub fn start_at(&self) -> Result<DateTime<Tz>, ParseError> {
+        Ok(DateTime::parse_from_rfc3339(&self.start)?.with_timezone(&self.tz().unwrap_or(Tz::UTC)))
+    }
+
+    /// End of the range in [`RangeData::tz`], the last second of the day.
+    pub fn end_at(&self) -> Result<DateTime<Tz>, ParseError> {
+        Ok(DateTime::parse_from_rfc3339(&self.end)?.with_timezone(&self.tz().unwrap_or(Tz::UTC)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SummariesData {
-    #[serde(default)]
-    pub branches: Vec<SummariesDetail>,
-    #[serde(default)]
-    pub entities: Vec<SummariesDetail>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branches: Option<Vec<SummariesDetail>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entities: Option<Vec<SummariesDetail>>,
     pub categories: Vec<SummariesDetail>,
     pub dependencies: Vec<SummariesDetail>,
     pub editors: Vec<SummariesDetail>,
     pub languages: Vec<SummariesDetail>,
     pub machines: Vec<SummariesDetail>,
-    #[serde(default)]
-    pub operating_system: Vec<SummariesDetail>,
+    /// `operating_system` in files of schema version 1
+    #[serde(default, alias = "operating_system")]
+    pub operating_systems: Vec<SummariesDetail>,
     #[serde(default)]
     pub projects: Vec<SummariesDetail>,
     pub grand_total: SummariesDetail,
     pub range: RangeData,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl SummariesData {
+    /// Dimensions of a day, named as in the API. Only the per-project
+    /// summaries have `branches` and `entities`.
+    pub const DIMENSIONS: [&'static str; 9] = [
+        "branches",
+        "categories",
+        "dependencies",
+        "editors",
+        "entities",
+        "languages",
+        "machines",
+        "operating_systems",
+        "projects",
+    ];
+
+    /// Entries of each of [`SummariesData::DIMENSIONS`], in that order.
+    pub fn dimensions(&self) -> [(&'static str, &[SummariesDetail]); 9] {
+        let entries: [&[SummariesDetail]; 9] = [
+            self.branches.as_deref().unwrap_or_default(),
+            &self.categories,
+            &self.dependencies,
+            &self.editors,
+            self.entities.as_deref().unwrap_or_default(),
+            &self.languages,
+            &self.machines,
+            &self.operating_systems,
+            &self.projects,
+        ];
+        std::array::from_fn(|i| (SummariesData::DIMENSIONS[i], entries[i]))
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Summaries {
     pub data: Vec<SummariesData>,
     pub start: String,
     pub end: String,
+    /// `cumulative_total`, `daily_average` and the like, about the whole range
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SummariesAll {
+    /// See [`SCHEMA_VERSION`]
+    #[serde(default = "first_schema_version")]
+    pub schema_version: u32,
     pub summaries: Summaries,
-    pub projects: HashMap<String, Summaries>,
+    /// keyed by project name, ordered so that saved files are reproducible
+    pub projects: BTreeMap<String, Summaries>,
 }
 
+impl SummariesAll {
+    /// Splits a multi-day result into one `SummariesAll` per day, keyed by
+    /// `range.date`, in the order returned by the API.
+    ///
+    /// Each day only keeps the project summaries of the projects worked on
+    /// that day, the same as a single-day request would return. The `extra`
+    /// fields of [`Summaries`] describe the whole range and are not copied.
+    pub fn split_by_day(self) -> Result<Vec<(NaiveDate, SummariesAll)>, ParseError> {
+        let mut project_days: HashMap<String, HashMap<String, SummariesData>> = HashMap::new();
+        for (name, proj) in self.projects {
+            let days = proj
+                .data
+                .into_iter()
+                .map(|d| (d.range.date.clone(), d))
+                .collect();
+            project_days.insert(name, days);
+        }
+
+        let mut res = Vec::with_capacity(self.summaries.data.len());
+        for day in self.summaries.data {
+            let date = day.range.parsed_date()?;
+            let mut projects = BTreeMap::new();
+            for p in &day.projects {
+                let proj_day = project_days
+                    .get_mut(&p.name)
+                    .and_then(|days| days.remove(&day.range.date));
+                if let Some(proj_day) = proj_day {
+                    projects.insert(
+                        p.name.clone(),
+                        Summaries {
+                            start: proj_day.range.start.clone(),
+                            end: proj_day.range.end.clone(),
+                            data: vec![proj_day],
+                            extra: Map::new(),
+                        },
+                    );
+                }
+            }
+            let summaries = Summaries {
+                start: day.range.start.clone(),
+                end: day.range.end.clone(),
+                data: vec![day],
+                extra: Map::new(),
+            };
+            res.push((
+                date,
+                SummariesAll {
+                    schema_version: self.schema_version,
+                    summaries,
+                    projects,
+                },
+            ));
+        }
+        Ok(res)
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::fixtures::detail;
+
+    fn day(date: &str, projects: &[&str]) -> serde_json::Value {
+        serde_json::json!({
+            "categories": [], "dependencies": [], "editors": [], "languages": [], "machines": [],
+            "projects": projects.iter().map(|p| detail(p, 60.0)).collect::<Vec<_>>(),
+            "grand_total": detail("", 60.0),
+            "range": {
+                "date": date,
+                "start": format!("{}T00:00:00Z", date),
+                "end": format!("{}T23:59:59Z", date),
+                "text": ""
+            }
+        })
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn split_by_day() {
+        let all: SummariesAll = serde_json::from_value(serde_json::json!({
+            "summaries": {
+                "data": [day("2021-02-22", &["a"]), day("2021-02-23", &["a", "b"])],
+                "start": "2021-02-22T00:00:00Z", "end": "2021-02-23T23:59:59Z"
+            },
+            "projects": {
+                "a": { "data": [day("2021-02-22", &["a"]), day("2021-02-23", &["a"])], "start": "", "end": "" },
+                "b": { "data": [day("2021-02-22", &[]), day("2021-02-23", &["b"])], "start": "", "end": "" }
+            }
+        }))
+        .unwrap();
+
+        let days = all.split_by_day().unwrap();
+        assert_eq!(days.len(), 2);
+
+        let (date, first) = &days[0];
+        assert_eq!(*date, NaiveDate::from_ymd_opt(2021, 2, 22).unwrap());
+        assert_eq!(first.summaries.data.len(), 1);
+        assert_eq!(first.summaries.start, "2021-02-22T00:00:00Z");
+        assert_eq!(first.projects.keys().collect::<Vec<_>>(), vec!["a"]);
+
+        let (_, second) = &days[1];
+        assert_eq!(second.projects.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(second.projects["b"].data[0].range.date, "2021-02-23");
+    }
+
+    #[test]
+    fn reads_the_range_in_its_timezone() {
+        let mut range: RangeData = serde_json::from_value(serde_json::json!({
+            "date": "2021-02-22", "start": "2021-02-21T15:00:00Z",
+            "end": "2021-02-22T14:59:59Z", "text": "Mon Feb 22nd 2021",
+            "timezone": "Asia/Tokyo"
+        }))
+        .unwrap();
+        assert_eq!(
+            range.parsed_date().unwrap(),
+            NaiveDate::from_ymd_opt(2021, 2, 22).unwrap()
+        );
+        assert_eq!(
+            range.start_at().unwrap().to_rfc3339(),
+            "2021-02-22T00:00:00+09:00"
+        );
+        assert_eq!(
+            range.end_at().unwrap().to_rfc3339(),
+            "2021-02-22T23:59:59+09:00"
+        );
+
+        range.timezone = None;
+        assert_eq!(range.tz(), None);
+        assert_eq!(
+            range.start_at().unwrap().to_rfc3339(),
+            "2021-02-21T15:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn keeps_fields_it_does_not_know() {
+        let entry = serde_json::json!({
+            "decimal": "1.05", "digital": "1:03", "hours": 1, "minutes": 3,
+            "name": "Rust", "percent": 52.31, "seconds": 12, "text": "1 hr 3 mins",
+            "total_seconds": 3792.456, "ai_additions": 4
+        });
+        let mut response = serde_json::json!({
+            "summaries": {
+                "data": [{
+                    "categories": [entry], "dependencies": [], "editors": [entry],
+                    "languages": [entry], "machines": [], "operating_systems": [entry],
+                    "projects": [],
+                    "grand_total": {
+                        "decimal": "1.05", "digital": "1:03", "hours": 1, "minutes": 3,
+                        "text": "1 hr 3 mins", "total_seconds": 3792.456
+                    },
+                    "range": {
+                        "date": "2021-02-22", "start": "2021-02-21T15:00:00Z",
+                        "end": "2021-02-22T14:59:59Z", "text": "Mon Feb 22nd 2021",
+                        "timezone": "Asia/Tokyo"
+                    }
+                }],
+                "start": "2021-02-21T15:00:00Z", "end": "2021-02-22T14:59:59Z",
+                "cumulative_total": { "seconds": 3792.456, "text": "1 hr 3 mins" }
+            },
+            "projects": {}
+        });
+
+        let all: SummariesAll = serde_json::from_value(response.clone()).unwrap();
+        assert_eq!(all.schema_version, 1);
+        assert_eq!(all.summaries.data[0].operating_systems.len(), 1);
+        assert_eq!(all.summaries.data[0].editors[0].extra["ai_additions"], 4);
+
+        response["schema_version"] = 1.into();
+        response["summaries"]["data"][0]["grand_total"]["name"] = "".into();
+        assert_eq!(serde_json::to_value(&all).unwrap(), response);
+    }
+
+    #[test]
+    fn reads_the_operating_systems_of_old_files() {
+        let mut old = day("2021-02-22", &[]);
+        old["operating_system"] = serde_json::json!([detail("Linux", 60.0)]);
+        let day: SummariesData = serde_json::from_value(old).unwrap();
+        assert_eq!(day.operating_systems[0].name, "Linux");
+        assert!(serde_json::to_value(&day).unwrap()["operating_systems"].is_array());
+    }
 }
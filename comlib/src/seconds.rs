@@ -0,0 +1,90 @@
+//! Durations as WakaTime reports them, in fractional seconds.
+
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub};
+
+use serde::{Deserialize, Serialize};
+
+/// A duration in seconds, kept as `f64` so that yearly totals do not lose the
+/// fractions an `f32` would.
+///
+/// Serialized as the bare number, like `total_seconds` in the API.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Seconds(pub f64);
+
+impl Seconds {
+    pub fn from_hours(hours: f64) -> Seconds {
+        Seconds(hours * 3600.0)
+    }
+
+    pub fn as_f64(self) -> f64 {
+        self.0
+    }
+
+    pub fn hours(self) -> f64 {
+        self.0 / 3600.0
+    }
+
+    /// `H:MM` with the minutes rounded down, as in the `digital` of the API.
+    pub fn hm(self) -> String {
+        let minutes = (self.0.max(0.0) / 60.0).floor() as u64;
+        format!("{}:{:02}", minutes / 60, minutes % 60)
+    }
+
+    /// Hours with two decimals, as in the `decimal` of the API.
+    pub fn decimal_hours(self) -> String {
+        format!("{:.2}", self.hours())
+    }
+}
+
+impl Add for Seconds {
+    type Output = Seconds;
+
+    fn add(self, other: Seconds) -> Seconds {
+        Seconds(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Seconds {
+    fn add_assign(&mut self, other: Seconds) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Seconds {
+    type Output = Seconds;
+
+    fn sub(self, other: Seconds) -> Seconds {
+        Seconds(self.0 - other.0)
+    }
+}
+
+impl Sum for Seconds {
+    fn sum<I: Iterator<Item = Seconds>>(iter: I) -> Seconds {
+        iter.fold(Seconds::default(), Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Seconds> for Seconds {
+    fn sum<I: Iterator<Item = &'a Seconds>>(iter: I) -> Seconds {
+        iter.copied().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_like_the_api() {
+        let secs = Seconds(3792.456);
+        assert_eq!(secs.hm(), "1:03");
+        assert_eq!(secs.decimal_hours(), "1.05");
+        assert_eq!(Seconds(59.9).hm(), "0:00");
+        assert_eq!(Seconds::from_hours(25.5).hm(), "25:30");
+
+        let year: Seconds = vec![Seconds(86_399.999_9); 365].iter().sum();
+        assert_eq!(year.hm(), "8759:59");
+    }
+}
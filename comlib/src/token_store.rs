@@ -0,0 +1,390 @@
+//! OAuth tokens issued by `get_auth`, stored as JSON in a file only the
+//! owner can read, and refreshed by [`refresh`].
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Token file used when `token-file` is not configured.
+pub const DEFAULT_TOKEN_FILE: &str = "token.json";
+
+/// Token endpoint of wakatime.com.
+pub const DEFAULT_TOKEN_URL: &str = "https://wakatime.com/oauth/token";
+
+/// Port of the redirect listener of `get_auth` when `oauth-port` is not
+/// configured.
+pub const DEFAULT_OAUTH_PORT: u16 = 8081;
+
+/// Tokens are treated as expired this long before they actually expire.
+const EXPIRY_LEEWAY_SECS: i64 = 60;
+
+/// OAuth tokens as stored on disk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OAuthToken {
+    pub access_token: String,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+impl OAuthToken {
+    pub fn load(path: &Path) -> Result<OAuthToken, TokenStoreError> {
+        let json = std::fs::read_to_string(path).map_err(|error| TokenStoreError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        serde_json::from_str(&json).map_err(|error| TokenStoreError::Format {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    /// Writes the token to `path`, readable and writable by the owner only.
+    pub fn save(&self, path: &Path) -> Result<(), TokenStoreError> {
+        let io_err = |error| TokenStoreError::Io {
+            path: path.to_path_buf(),
+            error,
+        };
+        let json = serde_json::to_string_pretty(self).map_err(|error| TokenStoreError::Format {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let mut file = open_private(path).map_err(io_err)?;
+        file.write_all(json.as_bytes()).map_err(io_err)?;
+        Ok(())
+    }
+
+    /// Whether the token has to be refreshed before it is used at `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        match self.expires_at {
+            Some(at) => at - Duration::seconds(EXPIRY_LEEWAY_SECS) <= now,
+            None => false,
+        }
+    }
+}
+
+/// Redirect URI of `get_auth login` listening on `port`. The token endpoint
+/// checks it again when a token is refreshed.
+pub fn redirect_uri(port: u16) -> String {
+    format!("http://localhost:{}", port)
+}
+
+/// Scopes of a `scope` value. WakaTime separates them with commas instead of
+/// the spaces of the OAuth specification, both are accepted.
+pub fn split_scopes(scope: &str) -> Vec<String> {
+    scope
+        .split([',', ' '])
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// OAuth application the tokens were issued to, needed to refresh them.
+#[derive(Debug, Clone)]
+pub struct OAuthApp {
+    pub client_id: String,
+    pub client_secret: String,
+    pub token_url: String,
+    /// [`redirect_uri`] of the port the tokens were issued with
+    pub redirect_uri: String,
+}
+
+/// Response of the token endpoint, WakaTime answers with JSON when asked to.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    expires_in: Option<i64>,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+impl TokenResponse {
+    /// Builds the new token, keeping the old refresh token and scopes when the
+    /// server does not send new ones.
+    fn into_token(self, prev: &OAuthToken, now: DateTime<Utc>) -> OAuthToken {
+        let expires_at = self
+            .expires_at
+            .or_else(|| self.expires_in.map(|secs| now + Duration::seconds(secs)));
+        OAuthToken {
+            access_token: self.access_token,
+            refresh_token: self.refresh_token.or_else(|| prev.refresh_token.clone()),
+            expires_at,
+            scopes: match self.scope {
+                Some(scope) => split_scopes(&scope),
+                None => prev.scopes.clone(),
+            },
+        }
+    }
+}
+
+fn parse_token_response(body: &str) -> Option<TokenResponse> {
+    if let Ok(res) = serde_json::from_str(body) {
+        return Some(res);
+    }
+    // the default answer of wakatime.com is form encoded
+    let pairs: HashMap<String, String> = url::form_urlencoded::parse(body.as_bytes())
+        .into_owned()
+        .collect();
+    Some(TokenResponse {
+        access_token: pairs.get("access_token")?.clone(),
+        refresh_token: pairs.get("refresh_token").cloned(),
+        expires_at: pairs
+            .get("expires_at")
+            .and_then(|s| s.parse::<DateTime<Utc>>().ok()),
+        expires_in: pairs.get("expires_in").and_then(|s| s.parse().ok()),
+        scope: pairs.get("scope").cloned(),
+    })
+}
+
+/// Exchanges the refresh token of `token` for a new access token.
+///
+/// `post` sends the form to the URL given and returns the status code and
+/// body of the answer, so that blocking and async clients share this flow.
+pub async fn refresh<F, Fut, E>(
+    app: &OAuthApp,
+    token: &OAuthToken,
+    post: F,
+) -> Result<OAuthToken, RefreshError<E>>
+where
+    F: FnOnce(String, Vec<(&'static str, String)>) -> Fut,
+    Fut: Future<Output = Result<(u16, String), E>>,
+{
+    let refresh_token = token
+        .refresh_token
+        .clone()
+        .ok_or(RefreshError::NoRefreshToken)?;
+    let form = vec![
+        ("client_id", app.client_id.clone()),
+        ("client_secret", app.client_secret.clone()),
+        ("grant_type", "refresh_token".to_string()),
+        ("refresh_token", refresh_token),
+        ("redirect_uri", app.redirect_uri.clone()),
+    ];
+    let (status, body) = post(app.token_url.clone(), form)
+        .await
+        .map_err(RefreshError::Send)?;
+    match parse_token_response(&body) {
+        Some(res) if status == 200 => Ok(res.into_token(token, Utc::now())),
+        _ => Err(RefreshError::Rejected { status, body }),
+    }
+}
+
+#[cfg(unix)]
+fn open_private(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // mode() only applies to newly created files
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn open_private(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::File::create(path)
+}
+
+/// Error when loading or saving the token file
+#[derive(Debug)]
+pub enum TokenStoreError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Format {
+        path: PathBuf,
+        error: serde_json::Error,
+    },
+}
+
+impl Error for TokenStoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TokenStoreError::Io { error, .. } => Some(error),
+            TokenStoreError::Format { error, .. } => Some(error),
+        }
+    }
+}
+
+/// Error of [`refresh`], `E` is the error of the client sending the request
+#[derive(Debug)]
+pub enum RefreshError<E> {
+    NoRefreshToken,
+    Send(E),
+    /// The token endpoint did not answer with a token
+    Rejected {
+        status: u16,
+        body: String,
+    },
+}
+
+impl<E: Error + 'static> Error for RefreshError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RefreshError::Send(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<E: Display> Display for RefreshError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            RefreshError::NoRefreshToken => write!(f, "no refresh token stored"),
+            RefreshError::Send(error) => write!(f, "token refresh request failed: {}", error),
+            RefreshError::Rejected { status, body } => write!(
+                f,
+                "token refresh failed => status code: {} ({})",
+                status, body
+            ),
+        }
+    }
+}
+
+impl Display for TokenStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            TokenStoreError::Io { path, error } => {
+                write!(f, "token file {}: {}", path.display(), error)
+            }
+            TokenStoreError::Format { path, error } => {
+                write!(f, "invalid token file {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(expires_at: Option<DateTime<Utc>>) -> OAuthToken {
+        OAuthToken {
+            access_token: "sec_old".into(),
+            refresh_token: Some("ref_old".into()),
+            expires_at,
+            scopes: vec!["read_stats".into()],
+        }
+    }
+
+    #[test]
+    fn expiry_has_leeway() {
+        let now = Utc::now();
+        assert!(!token(None).is_expired(now));
+        assert!(!token(Some(now + Duration::hours(1))).is_expired(now));
+        assert!(token(Some(now + Duration::seconds(30))).is_expired(now));
+        assert!(token(Some(now - Duration::hours(1))).is_expired(now));
+    }
+
+    #[test]
+    fn parses_json_response() {
+        let res = parse_token_response(
+            r#"{"access_token": "sec_new", "expires_in": 3600, "scope": "email,read_stats", "token_type": "bearer"}"#,
+        )
+        .unwrap();
+        let now = Utc::now();
+        let new = res.into_token(&token(None), now);
+        assert_eq!(new.access_token, "sec_new");
+        assert_eq!(new.refresh_token.as_deref(), Some("ref_old"));
+        assert_eq!(new.expires_at, Some(now + Duration::seconds(3600)));
+        assert_eq!(new.scopes, vec!["email", "read_stats"]);
+        assert_eq!(
+            split_scopes("email read_stats"),
+            vec!["email", "read_stats"]
+        );
+    }
+
+    #[test]
+    fn parses_form_response() {
+        let res = parse_token_response(
+            "access_token=sec_new&refresh_token=ref_new&expires_at=2030-01-01T00%3A00%3A00Z",
+        )
+        .unwrap();
+        let new = res.into_token(&token(None), Utc::now());
+        assert_eq!(new.refresh_token.as_deref(), Some("ref_new"));
+        assert_eq!(
+            new.expires_at,
+            Some("2030-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap())
+        );
+        assert_eq!(new.scopes, vec!["read_stats"]);
+        assert!(parse_token_response("error=invalid_grant").is_none());
+    }
+
+    #[test]
+    fn refreshes_with_the_redirect_uri() {
+        let app = OAuthApp {
+            client_id: "id".into(),
+            client_secret: "secret".into(),
+            token_url: DEFAULT_TOKEN_URL.into(),
+            redirect_uri: redirect_uri(8081),
+        };
+        let answer = |status: u16, body: &str| {
+            let res = Ok::<_, std::io::Error>((status, body.to_string()));
+            move |_: String, _: Vec<(&'static str, String)>| std::future::ready(res)
+        };
+        let mut sent = Vec::new();
+        let new = futures::executor::block_on(refresh(&app, &token(None), |url, form| {
+            sent = form;
+            answer(200, r#"{"access_token": "sec_new"}"#)(url, Vec::new())
+        }))
+        .unwrap();
+        assert_eq!(new.access_token, "sec_new");
+        assert!(sent.contains(&("redirect_uri", "http://localhost:8081".to_string())));
+        assert!(sent.contains(&("refresh_token", "ref_old".to_string())));
+
+        let rejected = futures::executor::block_on(refresh(
+            &app,
+            &token(None),
+            answer(400, "error=invalid_grant"),
+        ));
+        assert!(matches!(
+            rejected,
+            Err(RefreshError::Rejected { status: 400, .. })
+        ));
+        let no_refresh = OAuthToken {
+            refresh_token: None,
+            ..token(None)
+        };
+        let res = futures::executor::block_on(refresh(&app, &no_refresh, answer(200, "")));
+        assert!(matches!(res, Err(RefreshError::NoRefreshToken)));
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let path = std::env::temp_dir().join(format!("token_store_{}.json", std::process::id()));
+        let saved = token(Some("2030-01-01T00:00:00Z".parse().unwrap()));
+        saved.save(&path).unwrap();
+        let loaded = OAuthToken::load(&path);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), saved);
+        assert!(matches!(
+            OAuthToken::load(&path),
+            Err(TokenStoreError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,329 @@
+//! Consistency checks of a `SummariesAll` as returned by the API.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+use crate::{Seconds, SummariesAll, SummariesData};
+
+/// Seconds a dimension may differ from `grand_total` before it is reported,
+/// WakaTime rounds every entry on its own.
+pub const DEFAULT_TOLERANCE: Seconds = Seconds(1.0);
+
+/// Something in a `SummariesAll` that does not add up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// `range.date` of the day concerned
+    pub date: String,
+    /// Project summary the finding is in, `None` for the main summary
+    pub project: Option<String>,
+    pub kind: FindingKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FindingKind {
+    /// The entries of a dimension do not add up to `grand_total`
+    SumMismatch {
+        dimension: &'static str,
+        sum: Seconds,
+        grand_total: Seconds,
+    },
+    /// The project has time that day but is not in the projects of the main
+    /// summary
+    OrphanProject,
+    /// Negative or NaN seconds; `name` is empty for `grand_total`
+    InvalidSeconds {
+        dimension: &'static str,
+        name: String,
+        seconds: Seconds,
+    },
+    /// `range.date` is not a date, or `range.start` falls on another day in
+    /// the timezone of the range
+    RangeMismatch { start: String },
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.date)?;
+        if let Some(project) = &self.project {
+            write!(f, " project {}", project)?;
+        }
+        match &self.kind {
+            FindingKind::SumMismatch {
+                dimension,
+                sum,
+                grand_total,
+            } => write!(
+                f,
+                ": {} add up to {}s, grand_total is {}s",
+                dimension,
+                sum.as_f64(),
+                grand_total.as_f64()
+            ),
+            FindingKind::OrphanProject => write!(f, ": not in the projects of the day"),
+            FindingKind::InvalidSeconds {
+                dimension,
+                name,
+                seconds,
+            } => write!(f, ": {} {:?} has {}s", dimension, name, seconds.as_f64()),
+            FindingKind::RangeMismatch { start } => {
+                write!(f, ": range starts at {:?}, another day", start)
+            }
+        }
+    }
+}
+
+/// Counts of a run of [`validate`] over archive files, printed as its last
+/// line by `get_summary verify` and `bulk_regist --verify`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Report {
+    pub days: usize,
+    /// Files that could not be read
+    pub skipped: usize,
+    pub findings: usize,
+}
+
+impl Report {
+    /// Whether every file was read and nothing was found.
+    pub fn passed(&self) -> bool {
+        self.skipped == 0 && self.findings == 0
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{} day(s) checked, {} file(s) skipped, {} finding(s)",
+            self.days, self.skipped, self.findings
+        )
+    }
+}
+
+/// Checks the days of `all` and of its projects.
+///
+/// Dimensions a day does not have (older files have no `operating_systems`)
+/// are not compared with `grand_total`, neither are the dependencies and
+/// files which overlap each other.
+pub fn validate(all: &SummariesAll, tolerance: Seconds) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut worked: BTreeSet<(&str, &str)> = BTreeSet::new();
+    for day in &all.summaries.data {
+        check_day(day, None, tolerance, &mut findings);
+        for p in &day.projects {
+            worked.insert((day.range.date.as_str(), p.name.as_str()));
+        }
+    }
+    for (name, project) in &all.projects {
+        for day in &project.data {
+            check_day(day, Some(name), tolerance, &mut findings);
+            let has_time = day.grand_total.total_seconds.as_f64() > 0.0;
+            if has_time && !worked.contains(&(day.range.date.as_str(), name.as_str())) {
+                findings.push(Finding {
+                    date: day.range.date.clone(),
+                    project: Some(name.clone()),
+                    kind: FindingKind::OrphanProject,
+                });
+            }
+        }
+    }
+    findings
+}
+
+fn is_invalid(seconds: Seconds) -> bool {
+    seconds.as_f64().is_nan() || seconds.as_f64() < 0.0
+}
+
+fn check_day(
+    day: &SummariesData,
+    project: Option<&String>,
+    tolerance: Seconds,
+    findings: &mut Vec<Finding>,
+) {
+    let mut push = |kind| {
+        findings.push(Finding {
+            date: day.range.date.clone(),
+            project: project.cloned(),
+            kind,
+        })
+    };
+
+    let range_ok = match (day.range.parsed_date(), day.range.start_at()) {
+        (Err(_), _) => false,
+        // the day of the start is only known in the timezone of the user
+        (Ok(date), Ok(start)) if day.range.tz().is_some() => start.date_naive() == date,
+        // files written by hand or by old versions may have no start or
+        // timezone
+        (Ok(_), _) => true,
+    };
+    if !range_ok {
+        push(FindingKind::RangeMismatch {
+            start: day.range.start.clone(),
+        });
+    }
+
+    let grand_total = day.grand_total.total_seconds;
+    if is_invalid(grand_total) {
+        push(FindingKind::InvalidSeconds {
+            dimension: "grand_total",
+            name: String::new(),
+            seconds: grand_total,
+        });
+    }
+
+    for (dimension, entries) in day.dimensions() {
+        for entry in entries {
+            if is_invalid(entry.total_seconds) {
+                push(FindingKind::InvalidSeconds {
+                    dimension,
+                    name: entry.name.clone(),
+                    seconds: entry.total_seconds,
+                });
+            }
+        }
+        // dependencies and files overlap, and a project lists no projects
+        let adds_up = match dimension {
+            "dependencies" | "entities" => false,
+            "projects" => project.is_none(),
+            _ => true,
+        };
+        if !adds_up || entries.is_empty() {
+            continue;
+        }
+        let sum: Seconds = entries.iter().map(|e| e.total_seconds).sum();
+        if (sum - grand_total).as_f64().abs() > tolerance.as_f64() {
+            push(FindingKind::SumMismatch {
+                dimension,
+                sum,
+                grand_total,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::detail;
+    use serde_json::json;
+
+    fn day(date: &str, start: &str, langs: &[f64], projects: &[&str]) -> serde_json::Value {
+        json!({
+            "categories": [], "dependencies": [detail("serde", 9999.0)], "editors": [],
+            "languages": langs.iter().map(|s| detail("Rust", *s)).collect::<Vec<_>>(),
+            "machines": [],
+            "projects": projects.iter().map(|p| detail(p, 3600.0)).collect::<Vec<_>>(),
+            "grand_total": detail("", 3600.0),
+            "range": {
+                "date": date, "start": start, "end": "", "text": "", "timezone": "Asia/Tokyo"
+            }
+        })
+    }
+
+    #[test]
+    fn accepts_a_consistent_day() {
+        let all: SummariesAll = serde_json::from_value(json!({
+            "summaries": {
+                "data": [day("2021-02-22", "2021-02-21T15:00:00Z", &[3000.0, 600.4], &["a"])],
+                "start": "", "end": ""
+            },
+            "projects": { "a": { "data": [day("2021-02-22", "", &[3600.0], &[])], "start": "", "end": "" } }
+        }))
+        .unwrap();
+        assert_eq!(validate(&all, DEFAULT_TOLERANCE), vec![]);
+    }
+
+    #[test]
+    fn skips_the_start_without_a_timezone() {
+        let mut day = day("2021-02-22", "2021-02-21T15:00:00Z", &[3600.0], &[]);
+        day["range"]["timezone"] = json!(null);
+        let all: SummariesAll = serde_json::from_value(json!({
+            "summaries": { "data": [day.clone()], "start": "", "end": "" },
+            "projects": {}
+        }))
+        .unwrap();
+        assert_eq!(validate(&all, DEFAULT_TOLERANCE), vec![]);
+
+        day["range"]["timezone"] = json!("Mars/Olympus_Mons");
+        let all: SummariesAll = serde_json::from_value(json!({
+            "summaries": { "data": [day], "start": "", "end": "" },
+            "projects": {}
+        }))
+        .unwrap();
+        assert_eq!(validate(&all, DEFAULT_TOLERANCE), vec![]);
+    }
+
+    #[test]
+    fn fails_a_report_with_skipped_files_or_findings() {
+        let report = Report {
+            days: 3,
+            skipped: 0,
+            findings: 0,
+        };
+        assert!(report.passed());
+        assert_eq!(
+            report.to_string(),
+            "3 day(s) checked, 0 file(s) skipped, 0 finding(s)"
+        );
+        assert!(!Report {
+            skipped: 1,
+            ..report
+        }
+        .passed());
+        assert!(!Report {
+            findings: 1,
+            ..report
+        }
+        .passed());
+    }
+
+    #[test]
+    fn reports_each_kind_of_finding() {
+        let all: SummariesAll = serde_json::from_value(json!({
+            "summaries": {
+                "data": [
+                    day("2021-02-22", "2021-02-21T14:00:00Z", &[3000.0], &["a"]),
+                    day("2021-02-23", "", &[-1.0, 3601.0], &["a"]),
+                ],
+                "start": "", "end": ""
+            },
+            "projects": { "b": { "data": [day("2021-02-23", "", &[3600.0], &[])], "start": "", "end": "" } }
+        }))
+        .unwrap();
+        let findings = validate(&all, DEFAULT_TOLERANCE);
+        let kinds: Vec<(&str, Option<&str>, &FindingKind)> = findings
+            .iter()
+            .map(|f| (f.date.as_str(), f.project.as_deref(), &f.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    "2021-02-22",
+                    None,
+                    &FindingKind::RangeMismatch {
+                        start: "2021-02-21T14:00:00Z".into()
+                    }
+                ),
+                (
+                    "2021-02-22",
+                    None,
+                    &FindingKind::SumMismatch {
+                        dimension: "languages",
+                        sum: Seconds(3000.0),
+                        grand_total: Seconds(3600.0)
+                    }
+                ),
+                (
+                    "2021-02-23",
+                    None,
+                    &FindingKind::InvalidSeconds {
+                        dimension: "languages",
+                        name: "Rust".into(),
+                        seconds: Seconds(-1.0)
+                    }
+                ),
+                ("2021-02-23", Some("b"), &FindingKind::OrphanProject),
+            ]
+        );
+    }
+}
@@ -1,18 +1,26 @@
-use anyhow;
+use chrono::{Duration, Utc};
+use clap::{Args, Parser, Subcommand};
+use comlib::token_store::{
+    self, OAuthApp, OAuthToken, DEFAULT_OAUTH_PORT, DEFAULT_TOKEN_FILE, DEFAULT_TOKEN_URL,
+};
 use config::Config;
-use oauth2::basic::BasicClient;
+use oauth2::basic::{BasicClient, BasicTokenResponse};
+use oauth2::http::{header, method::Method, HeaderMap, HeaderValue};
 use oauth2::reqwest::http_client;
 use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, HttpRequest, PkceCodeChallenge,
+    RedirectUrl, Scope, TokenResponse, TokenUrl,
 };
 use once_cell::sync::Lazy;
 use std::{
-    io::{BufRead, BufReader, Write},
+    convert::TryFrom,
     net::TcpListener,
+    path::{Path, PathBuf},
 };
 use url::Url;
 
+mod redirect;
+
 static SETTINGS: Lazy<Config> = Lazy::new(|| {
     let mut settings = Config::default();
     settings
@@ -21,115 +29,288 @@ static SETTINGS: Lazy<Config> = Lazy::new(|| {
     settings
 });
 
-fn main() -> anyhow::Result<()> {
-    let app_id = SETTINGS.get_str("api-id")?;
-    let secret = SETTINGS.get_str("api-secret")?;
-    let authorize_url = "https://wakatime.com/oauth/authorize";
-    let token_url = "https://wakatime.com/oauth/token";
-
-    let client = BasicClient::new(
-        ClientId::new(app_id.into()),
-        Some(ClientSecret::new(secret.into())),
-        AuthUrl::new(authorize_url.into())?,
-        Some(TokenUrl::new(token_url.into())?),
+const AUTHORIZE_URL: &str = "https://wakatime.com/oauth/authorize";
+const REVOKE_URL: &str = "https://wakatime.com/oauth/revoke";
+const DEFAULT_SCOPES: &str = "email,read_logged_time,read_stats,read_orgs";
+const DEFAULT_TIMEOUT_SECS: u64 = 300;
+const MAX_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+
+/// Gets WakaTime OAuth tokens and keeps them in the token file.
+#[derive(Parser)]
+#[clap(name = "get_auth")]
+struct Cli {
+    /// Token file, defaults to `token-file` of Settings.toml or token.json
+    #[clap(long, global = true)]
+    token_file: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Authorize in the browser and store the issued tokens (default)
+    Login(LoginArgs),
+    /// Exchange the stored refresh token for a new access token
+    Refresh,
+    /// Revoke the stored tokens at WakaTime and delete the token file
+    Revoke,
+    /// Show the stored tokens' scopes and expiry
+    Status,
+}
+
+#[derive(Args, Default)]
+struct LoginArgs {
+    /// Port of the local redirect listener, must match the app's redirect URI
+    #[clap(long)]
+    port: Option<u16>,
+    /// Comma separated scopes to request
+    #[clap(long)]
+    scopes: Option<String>,
+    /// Seconds to wait for the redirect
+    #[clap(long)]
+    timeout: Option<u64>,
+    /// Do not listen for the redirect, paste the redirected URL instead
+    #[clap(long)]
+    headless: bool,
+}
+
+fn redirect_port() -> anyhow::Result<u16> {
+    match SETTINGS.get_int("oauth-port") {
+        Ok(port) => u16::try_from(port)
+            .map_err(|_| anyhow::anyhow!("oauth-port {} is not a port number", port)),
+        Err(_) => Ok(DEFAULT_OAUTH_PORT),
+    }
+}
+
+/// Seconds to wait for the redirect, `--timeout` or `oauth-timeout` of
+/// Settings.toml.
+fn redirect_timeout(arg: Option<u64>) -> anyhow::Result<u64> {
+    let secs = match arg {
+        Some(secs) => secs,
+        None => match SETTINGS.get_int("oauth-timeout") {
+            Ok(secs) => u64::try_from(secs).map_err(|_| {
+                anyhow::anyhow!("oauth-timeout {} is not a positive number of seconds", secs)
+            })?,
+            Err(_) => DEFAULT_TIMEOUT_SECS,
+        },
+    };
+    if !(1..=MAX_TIMEOUT_SECS).contains(&secs) {
+        anyhow::bail!(
+            "timeout of {} seconds is not between 1 and {}",
+            secs,
+            MAX_TIMEOUT_SECS
+        );
+    }
+    Ok(secs)
+}
+
+fn oauth_app(port: u16) -> anyhow::Result<OAuthApp> {
+    Ok(OAuthApp {
+        client_id: SETTINGS.get_str("api-id")?,
+        client_secret: SETTINGS.get_str("api-secret")?,
+        token_url: SETTINGS
+            .get_str("oauth-token-url")
+            .unwrap_or_else(|_| DEFAULT_TOKEN_URL.to_string()),
+        redirect_uri: token_store::redirect_uri(port),
+    })
+}
+
+fn oauth_client(port: u16) -> anyhow::Result<BasicClient> {
+    let app = oauth_app(port)?;
+    Ok(BasicClient::new(
+        ClientId::new(app.client_id),
+        Some(ClientSecret::new(app.client_secret)),
+        AuthUrl::new(AUTHORIZE_URL.into())?,
+        Some(TokenUrl::new(app.token_url)?),
     )
-    .set_redirect_url(
-        RedirectUrl::new("http://localhost:8081".into()).expect("Invalid redirect URL"),
+    .set_redirect_url(RedirectUrl::new(app.redirect_uri).expect("Invalid redirect URL")))
+}
+
+/// Posts `form` to `url`, returning the status code and body of the answer.
+fn post_form(url: &str, form: &[(&str, String)]) -> anyhow::Result<(u16, String)> {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(form)
+        .finish();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-www-form-urlencoded"),
     );
+    headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+    let res = http_client(HttpRequest {
+        url: Url::parse(url)?,
+        method: Method::POST,
+        headers,
+        body: body.into_bytes(),
+    })
+    .map_err(|e| anyhow::anyhow!("request to {} failed: {:?}", url, e))?;
+    Ok((
+        res.status_code.as_u16(),
+        String::from_utf8_lossy(&res.body).into_owned(),
+    ))
+}
+
+/// Converts the token endpoint's answer into what is stored, keeping the
+/// refresh token and scopes of `prev` when the server does not send new ones.
+fn to_stored(token: &BasicTokenResponse, prev: Option<&OAuthToken>) -> OAuthToken {
+    let scopes = match token.scopes() {
+        Some(scopes) => scopes
+            .iter()
+            .flat_map(|scope| token_store::split_scopes(scope))
+            .collect(),
+        None => prev.map(|p| p.scopes.clone()).unwrap_or_default(),
+    };
+    OAuthToken {
+        access_token: token.access_token().secret().clone(),
+        refresh_token: token
+            .refresh_token()
+            .map(|t| t.secret().clone())
+            .or_else(|| prev.and_then(|p| p.refresh_token.clone())),
+        expires_at: token
+            .expires_in()
+            .and_then(|d| Duration::from_std(d).ok())
+            .map(|d| Utc::now() + d),
+        scopes,
+    }
+}
 
-    //let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+fn login(path: &Path, args: &LoginArgs) -> anyhow::Result<()> {
+    let port = match args.port {
+        Some(port) => port,
+        None => redirect_port()?,
+    };
+    let scopes = args
+        .scopes
+        .clone()
+        .or_else(|| SETTINGS.get_str("oauth-scopes").ok())
+        .unwrap_or_else(|| DEFAULT_SCOPES.to_string());
+    let timeout = std::time::Duration::from_secs(redirect_timeout(args.timeout)?);
+    let client = oauth_client(port)?;
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
 
     let (auth_url, csrf_state) = client
         .authorize_url(CsrfToken::new_random)
-        // Set the desired scopes.
-        .add_scope(Scope::new(
-            "email,read_logged_time,read_stats,read_orgs".to_string(),
-        ))
-        // Set the PKCE code challenge
-        //.set_pkce_challenge(pkce_challenge)
+        // WakaTime takes the scopes as one comma separated value
+        .add_scope(Scope::new(scopes))
+        .set_pkce_challenge(pkce_challenge)
         .url();
 
     println!("Browse to: {}", auth_url);
 
-    // A very naive implementation of the redirect server.
-    let listener = TcpListener::bind("127.0.0.1:8081").unwrap();
-    for stream in listener.incoming() {
-        if let Ok(mut stream) = stream {
-            let code;
-            let state;
-            {
-                let mut reader = BufReader::new(&stream);
-
-                let mut request_line = String::new();
-                reader.read_line(&mut request_line).unwrap();
-
-                let redirect_url = request_line.split_whitespace().nth(1).unwrap();
-                let url = Url::parse(&("http://localhost".to_string() + redirect_url)).unwrap();
-
-                let code_pair = url
-                    .query_pairs()
-                    .find(|pair| {
-                        let &(ref key, _) = pair;
-                        key == "code"
-                    })
-                    .unwrap();
-
-                let (_, value) = code_pair;
-                code = AuthorizationCode::new(value.into_owned());
-
-                let state_pair = url
-                    .query_pairs()
-                    .find(|pair| {
-                        let &(ref key, _) = pair;
-                        key == "state"
-                    })
-                    .unwrap();
-
-                let (_, value) = state_pair;
-                state = CsrfToken::new(value.into_owned());
-            }
-
-            let message = "Go back to your terminal :)";
-            let response = format!(
-                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
-                message.len(),
-                message
-            );
-            stream.write_all(response.as_bytes()).unwrap();
-
-            println!("Github returned the following code:\n{}\n", code.secret());
-            println!(
-                "Github returned the following state:\n{} (expected `{}`)\n",
-                state.secret(),
-                csrf_state.secret()
-            );
-
-            // Exchange the code with a token.
-            let token_res = client.exchange_code(code).request(http_client);
-
-            println!("Github returned the following token:\n{:?}\n", token_res);
-
-            if let Ok(token) = token_res {
-                // NB: Github returns a single comma-separated "scope" parameter instead of multiple
-                // space-separated scopes. Github-specific clients can parse this scope into
-                // multiple scopes by splitting at the commas. Note that it's not safe for the
-                // library to do this by default because RFC 6749 allows scopes to contain commas.
-                let scopes = if let Some(scopes_vec) = token.scopes() {
-                    scopes_vec
-                        .iter()
-                        .map(|comma_separated| comma_separated.split(','))
-                        .flatten()
-                        .collect::<Vec<_>>()
-                } else {
-                    Vec::new()
-                };
-                println!("Github returned the following scopes:\n{:?}\n", scopes);
-            }
-
-            // The server will terminate itself after collecting the first code.
-            break;
-        }
+    let redirect = if args.headless {
+        println!("After authorizing, paste the URL the browser was redirected to (it fails to load, that is fine):");
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        let url = redirect::parse_pasted(&line)?;
+        redirect::parse_redirect(&url, csrf_state.secret())?
+    } else {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!(
+            "Waiting for the redirect on port {} ({} seconds)...",
+            port,
+            timeout.as_secs()
+        );
+        redirect::accept_redirect(&listener, timeout, csrf_state.secret())?
+    };
+
+    // Exchange the code with a token.
+    let token = client
+        .exchange_code(AuthorizationCode::new(redirect.code))
+        .set_pkce_verifier(pkce_verifier)
+        .request(http_client)
+        .map_err(|e| anyhow::anyhow!("token exchange failed: {:?}", e))?;
+
+    let stored = to_stored(&token, None);
+    stored.save(path)?;
+    println!("token saved to {}", path.display());
+    print_status(&stored);
+    Ok(())
+}
+
+fn refresh(path: &Path) -> anyhow::Result<()> {
+    let stored = OAuthToken::load(path)?;
+    if stored.refresh_token.is_none() {
+        anyhow::bail!(
+            "{} has no refresh token, run get_auth login",
+            path.display()
+        );
+    }
+    let app = oauth_app(redirect_port()?)?;
+    let refreshed =
+        futures::executor::block_on(token_store::refresh(&app, &stored, |url, form| {
+            std::future::ready(post_form(&url, &form))
+        }))
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    refreshed.save(path)?;
+    println!("token refreshed and saved to {}", path.display());
+    print_status(&refreshed);
+    Ok(())
+}
+
+fn revoke(path: &Path) -> anyhow::Result<()> {
+    let stored = OAuthToken::load(path)?;
+    let (status, body) = post_form(
+        REVOKE_URL,
+        &[
+            ("client_id", SETTINGS.get_str("api-id")?),
+            ("client_secret", SETTINGS.get_str("api-secret")?),
+            ("token", stored.access_token),
+        ],
+    )?;
+    if !(200..300).contains(&status) {
+        anyhow::bail!("revoke failed => status code: {} ({})", status, body);
     }
 
+    std::fs::remove_file(path)?;
+    println!("token revoked, {} deleted", path.display());
     Ok(())
 }
+
+fn status(path: &Path) -> anyhow::Result<()> {
+    let stored = OAuthToken::load(path)?;
+    println!("token file    : {}", path.display());
+    print_status(&stored);
+    Ok(())
+}
+
+fn print_status(token: &OAuthToken) {
+    let now = Utc::now();
+    let shown: String = token.access_token.chars().take(8).collect();
+    println!("access token  : {}...", shown);
+    println!(
+        "refresh token : {}",
+        if token.refresh_token.is_some() {
+            "stored"
+        } else {
+            "none"
+        }
+    );
+    match token.expires_at {
+        Some(at) if token.is_expired(now) => println!("expires at    : {} (expired)", at),
+        Some(at) => println!(
+            "expires at    : {} (in {} minutes)",
+            at,
+            (at - now).num_minutes()
+        ),
+        None => println!("expires at    : unknown"),
+    }
+    println!("scopes        : {}", token.scopes.join(", "));
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let path = cli
+        .token_file
+        .or_else(|| SETTINGS.get_str("token-file").ok().map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_TOKEN_FILE));
+
+    match cli
+        .command
+        .unwrap_or_else(|| Command::Login(LoginArgs::default()))
+    {
+        Command::Login(args) => login(&path, &args),
+        Command::Refresh => refresh(&path),
+        Command::Revoke => revoke(&path),
+        Command::Status => status(&path),
+    }
+}
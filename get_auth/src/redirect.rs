@@ -0,0 +1,305 @@
+//! Receiving the authorization code that WakaTime redirects the browser to.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+/// Result of a successful authorization redirect.
+#[derive(Debug, PartialEq)]
+pub struct Redirect {
+    pub code: String,
+}
+
+/// Error when handling the authorization redirect
+#[derive(Debug)]
+pub enum RedirectError {
+    /// The user or WakaTime refused the authorization (`error=` redirect)
+    Denied {
+        error: String,
+        description: Option<String>,
+    },
+    /// A required query parameter is not present
+    Missing(&'static str),
+    /// `state` differs from the one sent, the redirect was not started by us
+    StateMismatch,
+    /// The request or pasted text is not a redirect URL
+    InvalidUrl(String),
+    /// Nothing arrived within the listen timeout
+    Timeout(Duration),
+    Io(std::io::Error),
+}
+
+impl Error for RedirectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RedirectError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RedirectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            RedirectError::Denied { error, description } => match description {
+                Some(description) => write!(f, "authorization denied: {} ({})", error, description),
+                None => write!(f, "authorization denied: {}", error),
+            },
+            RedirectError::Missing(name) => write!(f, "redirect has no `{}` parameter", name),
+            RedirectError::StateMismatch => {
+                write!(f, "state of the redirect does not match, possible CSRF")
+            }
+            RedirectError::InvalidUrl(url) => write!(f, "invalid redirect url [{}]", url),
+            RedirectError::Timeout(d) => {
+                write!(f, "no redirect received within {} seconds", d.as_secs())
+            }
+            RedirectError::Io(error) => write!(f, "redirect listener: {}", error),
+        }
+    }
+}
+
+impl From<std::io::Error> for RedirectError {
+    fn from(error: std::io::Error) -> Self {
+        RedirectError::Io(error)
+    }
+}
+
+/// Checks the redirect URL against the `state` sent in the authorization
+/// request and takes the code out of it.
+pub fn parse_redirect(url: &Url, expected_state: &str) -> Result<Redirect, RedirectError> {
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    if let Some(error) = param("error") {
+        return Err(RedirectError::Denied {
+            error,
+            description: param("error_description"),
+        });
+    }
+    let state = param("state").ok_or(RedirectError::Missing("state"))?;
+    if !constant_time_eq(state.as_bytes(), expected_state.as_bytes()) {
+        return Err(RedirectError::StateMismatch);
+    }
+    let code = param("code").ok_or(RedirectError::Missing("code"))?;
+    Ok(Redirect { code })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Parses what the user pasted in headless mode, either the whole redirected
+/// URL or only its query string.
+pub fn parse_pasted(input: &str) -> Result<Url, RedirectError> {
+    let input = input.trim();
+    let parsed = if input.contains("://") {
+        Url::parse(input)
+    } else {
+        let query = input.trim_start_matches(['/', '?']);
+        Url::parse(&format!("http://localhost/?{}", query))
+    };
+    parsed.map_err(|_| RedirectError::InvalidUrl(input.to_string()))
+}
+
+/// Extracts the URL from the request line of the browser's HTTP request.
+fn parse_request_line(line: &str) -> Result<Url, RedirectError> {
+    let invalid = || RedirectError::InvalidUrl(line.trim().to_string());
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => {
+            Url::parse(&format!("http://localhost{}", target)).map_err(|_| invalid())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Waits on `listener` for the browser to be redirected back, answering with
+/// an HTML page that tells the user the outcome.
+///
+/// Requests that are not the redirect (e.g. `/favicon.ico`) get a 404 and
+/// waiting goes on until `timeout` has passed.
+pub fn accept_redirect(
+    listener: &TcpListener,
+    timeout: Duration,
+    expected_state: &str,
+) -> Result<Redirect, RedirectError> {
+    let deadline = Instant::now() + timeout;
+    listener.set_nonblocking(true)?;
+    loop {
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(RedirectError::Timeout(timeout));
+                }
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut request_line = String::new();
+        if BufReader::new(&stream)
+            .read_line(&mut request_line)
+            .is_err()
+        {
+            continue;
+        }
+        let url = match parse_request_line(&request_line) {
+            Ok(url) => url,
+            Err(_) => {
+                respond(&mut stream, "400 Bad Request", "");
+                continue;
+            }
+        };
+        // only a request carrying OAuth parameters is the redirect
+        if !url
+            .query_pairs()
+            .any(|(key, _)| key == "code" || key == "state" || key == "error")
+        {
+            respond(&mut stream, "404 Not Found", "");
+            continue;
+        }
+
+        let result = parse_redirect(&url, expected_state);
+        match &result {
+            Ok(_) => respond(&mut stream, "200 OK", &success_page()),
+            Err(e) => respond(
+                &mut stream,
+                "400 Bad Request",
+                &failure_page(&e.to_string()),
+            ),
+        }
+        return result;
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, html: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-type: text/html; charset=utf-8\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        html.len(),
+        html
+    );
+    // the browser going away does not change the outcome
+    let _ = stream.write_all(response.as_bytes());
+}
+
+fn page(title: &str, message: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\
+         <body style=\"font-family: sans-serif; text-align: center; margin-top: 4em\">\
+         <h1>{0}</h1><p>{1}</p></body></html>",
+        html_escape(title),
+        html_escape(message)
+    )
+}
+
+fn success_page() -> String {
+    page(
+        "WakaTime authorization complete",
+        "You can close this window and go back to your terminal.",
+    )
+}
+
+fn failure_page(reason: &str) -> String {
+    page("WakaTime authorization failed", reason)
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn accepts_matching_state() {
+        let r = parse_redirect(&url("http://localhost:8081/?code=abc&state=xyz"), "xyz");
+        assert_eq!(r.unwrap(), Redirect { code: "abc".into() });
+    }
+
+    #[test]
+    fn rejects_bad_redirects() {
+        assert!(matches!(
+            parse_redirect(&url("http://localhost/?code=abc&state=evil"), "xyz"),
+            Err(RedirectError::StateMismatch)
+        ));
+        assert!(matches!(
+            parse_redirect(&url("http://localhost/?code=abc"), "xyz"),
+            Err(RedirectError::Missing("state"))
+        ));
+        assert!(matches!(
+            parse_redirect(&url("http://localhost/?state=xyz"), "xyz"),
+            Err(RedirectError::Missing("code"))
+        ));
+        match parse_redirect(
+            &url("http://localhost/?error=access_denied&error_description=User+denied&state=xyz"),
+            "xyz",
+        ) {
+            Err(RedirectError::Denied { error, description }) => {
+                assert_eq!(error, "access_denied");
+                assert_eq!(description.as_deref(), Some("User denied"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_pasted_input() {
+        let full = parse_pasted("  http://localhost:8081/?code=abc&state=xyz\n").unwrap();
+        let query = parse_pasted("?code=abc&state=xyz").unwrap();
+        assert_eq!(parse_redirect(&full, "xyz").unwrap().code, "abc");
+        assert_eq!(parse_redirect(&query, "xyz").unwrap().code, "abc");
+    }
+
+    #[test]
+    fn serves_the_redirect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let browser = std::thread::spawn(move || {
+            let get = |path: &str| {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                write!(stream, "GET {} HTTP/1.1\r\nhost: localhost\r\n\r\n", path).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            };
+            let favicon = get("/favicon.ico");
+            let redirect = get("/?code=abc&state=xyz");
+            (favicon, redirect)
+        });
+        let r = accept_redirect(&listener, Duration::from_secs(10), "xyz").unwrap();
+        assert_eq!(r.code, "abc");
+        let (favicon, redirect) = browser.join().unwrap();
+        assert!(favicon.starts_with("HTTP/1.1 404"));
+        assert!(redirect.starts_with("HTTP/1.1 200"));
+        assert!(redirect.contains("authorization complete"));
+    }
+
+    #[test]
+    fn times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(matches!(
+            accept_redirect(&listener, Duration::from_millis(200), "xyz"),
+            Err(RedirectError::Timeout(_))
+        ));
+    }
+}
@@ -0,0 +1,706 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use comlib::Summaries;
+use reqwest::{header, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::oauth::TokenSource;
+use crate::retry::{RateLimiter, RetryPolicy};
+
+/// Base URL of the public WakaTime API.
+pub const DEFAULT_BASE_URL: &str = "https://wakatime.com/api/v1/";
+
+/// Credentials sent with every request.
+#[derive(Debug, Clone)]
+pub enum Auth {
+    /// `secret-api-key` of the account, sent as HTTP Basic auth.
+    ApiKey(String),
+    /// OAuth access token, sent as Bearer auth and refreshed when it expires.
+    OAuth(Arc<TokenSource>),
+}
+
+impl Auth {
+    async fn header_value(&self, http: &reqwest::Client) -> Result<String, WakatimeError> {
+        match self {
+            Auth::ApiKey(key) => Ok(format!("Basic {}", base64::encode(key))),
+            Auth::OAuth(source) => source.header_value(http).await,
+        }
+    }
+}
+
+/// Client for the WakaTime API.
+///
+/// The client is cheap to clone and shares one connection pool and one request
+/// budget between clones, so build it once and pass it around.
+#[derive(Debug, Clone)]
+pub struct WakatimeClient {
+    http: reqwest::Client,
+    base_url: Url,
+    auth: Auth,
+    retry: RetryPolicy,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+/// Builder for [`WakatimeClient`].
+#[derive(Debug, Default)]
+pub struct WakatimeClientBuilder {
+    base_url: Option<String>,
+    auth: Option<Auth>,
+    timeout: Option<Duration>,
+    retry: Option<RetryPolicy>,
+    requests_per_minute: Option<u32>,
+}
+
+impl WakatimeClientBuilder {
+    /// Overrides the API base URL, e.g. a mock server or a self-hosted
+    /// WakaTime-compatible server. Defaults to [`DEFAULT_BASE_URL`].
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    /// Authenticates with the account's secret API key.
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.auth = Some(Auth::ApiKey(key.into()));
+        self
+    }
+
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Timeout of a single HTTP request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retries for 429, 5xx and connection errors. Defaults to
+    /// [`RetryPolicy::default`], use [`RetryPolicy::none`] to fail fast.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Global budget shared by all clones of the client. Unlimited by default.
+    pub fn requests_per_minute(mut self, limit: u32) -> Self {
+        self.requests_per_minute = Some(limit);
+        self
+    }
+
+    pub fn build(self) -> Result<WakatimeClient, WakatimeError> {
+        let auth = self.auth.ok_or(WakatimeError::MissingCredentials)?;
+        let mut base = self
+            .base_url
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        // Url::join drops the last path segment unless the base ends with '/'
+        if !base.ends_with('/') {
+            base.push('/');
+        }
+        let base_url = Url::parse(&base).map_err(|error| WakatimeError::InvalidUrl {
+            url: base.clone(),
+            error,
+        })?;
+
+        let mut http = reqwest::Client::builder();
+        if let Some(timeout) = self.timeout {
+            http = http.timeout(timeout);
+        }
+        let http = http.build().map_err(WakatimeError::Http)?;
+
+        Ok(WakatimeClient {
+            http,
+            base_url,
+            auth,
+            retry: self.retry.unwrap_or_default(),
+            limiter: self
+                .requests_per_minute
+                .map(|limit| Arc::new(RateLimiter::new(limit))),
+        })
+    }
+}
+
+impl WakatimeClient {
+    pub fn builder() -> WakatimeClientBuilder {
+        WakatimeClientBuilder::default()
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// `GET /users/current/summaries` for `start..=end`.
+    pub fn summaries(&self, start: NaiveDate, end: NaiveDate) -> SummariesRequest<'_> {
+        SummariesRequest {
+            client: self,
+            start,
+            end,
+            project: None,
+            timezone: None,
+        }
+    }
+
+    /// `GET /users/current/projects`.
+    pub fn projects(&self) -> ProjectsRequest<'_> {
+        ProjectsRequest {
+            client: self,
+            query: None,
+        }
+    }
+
+    fn endpoint(&self, path: &str) -> Result<Url, WakatimeError> {
+        self.base_url
+            .join(path)
+            .map_err(|error| WakatimeError::InvalidUrl {
+                url: format!("{}{}", self.base_url, path),
+                error,
+            })
+    }
+
+    /// Sends the request, retrying transient failures according to the policy.
+    async fn get_json<T: DeserializeOwned>(&self, url: Url) -> Result<T, WakatimeError> {
+        let mut retry = 0;
+        let mut refreshed = false;
+        loop {
+            let err = match self.get_json_once(&url).await {
+                Ok(v) => return Ok(v),
+                Err(err) => err,
+            };
+            // a revoked or early expired access token, refresh it once
+            if let (WakatimeError::Unauthorized { .. }, Auth::OAuth(source)) = (&err, &self.auth) {
+                if !refreshed && source.invalidate().await {
+                    refreshed = true;
+                    continue;
+                }
+            }
+            if !err.is_retryable() || retry >= self.retry.max_retries {
+                return Err(if retry == 0 {
+                    err
+                } else {
+                    WakatimeError::Exhausted {
+                        attempts: retry + 1,
+                        last: Box::new(err),
+                    }
+                });
+            }
+            let wait = match &err {
+                WakatimeError::RateLimited {
+                    retry_after: Some(wait),
+                } => self.retry.retry_after(*wait),
+                _ => self.retry.backoff(retry),
+            };
+            if let (WakatimeError::RateLimited { .. }, Some(limiter)) = (&err, &self.limiter) {
+                limiter.pause_for(wait);
+            }
+            tokio::time::sleep(wait).await;
+            retry += 1;
+        }
+    }
+
+    async fn get_json_once<T: DeserializeOwned>(&self, url: &Url) -> Result<T, WakatimeError> {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+        let res = self
+            .http
+            .get(url.clone())
+            .header(
+                header::AUTHORIZATION,
+                self.auth.header_value(&self.http).await?,
+            )
+            .send()
+            .await
+            .map_err(WakatimeError::Http)?;
+        let status = res.status();
+        let retry_after = res
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = res.text().await.map_err(WakatimeError::Http)?;
+        if let Some(err) = WakatimeError::from_status(status, url, retry_after, &body) {
+            return Err(err);
+        }
+        serde_json::from_str(&body).map_err(|error| WakatimeError::Decode { error, body })
+    }
+}
+
+/// Request builder for the summaries endpoint.
+#[derive(Debug, Clone)]
+pub struct SummariesRequest<'a> {
+    client: &'a WakatimeClient,
+    start: NaiveDate,
+    end: NaiveDate,
+    project: Option<String>,
+    timezone: Option<String>,
+}
+
+impl<'a> SummariesRequest<'a> {
+    /// Restricts the summaries to one project. The response then also carries
+    /// `branches` and `entities`.
+    pub fn project(mut self, name: impl Into<String>) -> Self {
+        self.project = Some(name.into());
+        self
+    }
+
+    /// IANA timezone used to split days, defaults to the account's timezone.
+    pub fn timezone(mut self, tz: impl Into<String>) -> Self {
+        self.timezone = Some(tz.into());
+        self
+    }
+
+    pub fn url(&self) -> Result<Url, WakatimeError> {
+        let mut url = self.client.endpoint("users/current/summaries")?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("start", &self.start.format("%Y-%m-%d").to_string())
+                .append_pair("end", &self.end.format("%Y-%m-%d").to_string());
+            if let Some(project) = &self.project {
+                query.append_pair("project", project);
+            }
+            if let Some(tz) = &self.timezone {
+                query.append_pair("timezone", tz);
+            }
+        }
+        Ok(url)
+    }
+
+    pub async fn send(self) -> Result<Summaries, WakatimeError> {
+        let url = self.url()?;
+        self.client.get_json(url).await
+    }
+}
+
+/// Request builder for the projects endpoint.
+#[derive(Debug, Clone)]
+pub struct ProjectsRequest<'a> {
+    client: &'a WakatimeClient,
+    query: Option<String>,
+}
+
+impl<'a> ProjectsRequest<'a> {
+    /// Filters projects by name.
+    pub fn query(mut self, q: impl Into<String>) -> Self {
+        self.query = Some(q.into());
+        self
+    }
+
+    pub fn url(&self) -> Result<Url, WakatimeError> {
+        let mut url = self.client.endpoint("users/current/projects")?;
+        if let Some(q) = &self.query {
+            url.query_pairs_mut().append_pair("q", q);
+        }
+        Ok(url)
+    }
+
+    pub async fn send(self) -> Result<Vec<Project>, WakatimeError> {
+        let url = self.url()?;
+        let res: ProjectsResponse = self.client.get_json(url).await?;
+        Ok(res.data)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub repository: Option<serde_json::Value>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub last_heartbeat_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectsResponse {
+    data: Vec<Project>,
+}
+
+/// `Retry-After` is either delta-seconds or an HTTP date; WakaTime sends seconds.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// Errors returned by [`WakatimeClient`].
+#[derive(Debug)]
+pub enum WakatimeError {
+    /// Neither an API key nor any other credential was configured
+    MissingCredentials,
+    /// The base URL or an endpoint URL could not be built
+    InvalidUrl { url: String, error: url::ParseError },
+    /// Connection, TLS or timeout error
+    Http(reqwest::Error),
+    /// 401 / 403, the credentials were rejected
+    Unauthorized { status: StatusCode, body: String },
+    /// 404, unknown user or endpoint
+    NotFound { url: String },
+    /// 429, `retry_after` is taken from the `Retry-After` header
+    RateLimited { retry_after: Option<Duration> },
+    /// Any other non-success status
+    Status { status: StatusCode, body: String },
+    /// The body was not the expected JSON document
+    Decode {
+        error: serde_json::Error,
+        body: String,
+    },
+    /// The OAuth access token expired and could not be refreshed
+    TokenRefresh {
+        status: Option<StatusCode>,
+        body: String,
+    },
+    /// Every attempt failed, `last` is the error of the final attempt
+    Exhausted {
+        attempts: u32,
+        last: Box<WakatimeError>,
+    },
+}
+
+impl WakatimeError {
+    /// Whether sending the same request again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            WakatimeError::RateLimited { .. } => true,
+            WakatimeError::Status { status, .. } => status.is_server_error(),
+            WakatimeError::Http(error) => !error.is_builder() && !error.is_redirect(),
+            _ => false,
+        }
+    }
+
+    /// Maps a non-success status to an error, `None` for 2xx.
+    fn from_status(
+        status: StatusCode,
+        url: &Url,
+        retry_after: Option<Duration>,
+        body: &str,
+    ) -> Option<WakatimeError> {
+        if status.is_success() {
+            return None;
+        }
+        let err = match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => WakatimeError::Unauthorized {
+                status,
+                body: body.to_string(),
+            },
+            StatusCode::NOT_FOUND => WakatimeError::NotFound {
+                url: url.to_string(),
+            },
+            StatusCode::TOO_MANY_REQUESTS => WakatimeError::RateLimited { retry_after },
+            _ => WakatimeError::Status {
+                status,
+                body: body.to_string(),
+            },
+        };
+        Some(err)
+    }
+}
+
+impl Error for WakatimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WakatimeError::InvalidUrl { error, .. } => Some(error),
+            WakatimeError::Http(error) => Some(error),
+            WakatimeError::Decode { error, .. } => Some(error),
+            WakatimeError::Exhausted { last, .. } => Some(last.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Display for WakatimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            WakatimeError::MissingCredentials => write!(f, "no WakaTime credentials configured"),
+            WakatimeError::InvalidUrl { url, error } => {
+                write!(f, "invalid url [{}]: {}", url, error)
+            }
+            WakatimeError::Http(error) => write!(f, "http error: {}", error),
+            WakatimeError::Unauthorized { status, .. } => {
+                write!(f, "authentication failed => status code: {}", status)
+            }
+            WakatimeError::NotFound { url } => write!(f, "not found: {}", url),
+            WakatimeError::RateLimited { retry_after } => match retry_after {
+                Some(d) => write!(f, "rate limited, retry after {}s", d.as_secs()),
+                None => write!(f, "rate limited"),
+            },
+            WakatimeError::Status { status, .. } => {
+                write!(f, "bad request => status code: {}", status)
+            }
+            WakatimeError::Decode { error, body } => {
+                write!(f, "invalid response body: {} (body: {})", error, body)
+            }
+            WakatimeError::TokenRefresh { status, body } => match status {
+                Some(status) => write!(
+                    f,
+                    "token refresh failed => status code: {} ({})",
+                    status, body
+                ),
+                None => write!(f, "token refresh failed: {}", body),
+            },
+            WakatimeError::Exhausted { attempts, last } => {
+                write!(f, "gave up after {} attempts: {}", attempts, last)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn client(base: &str) -> WakatimeClient {
+        WakatimeClient::builder()
+            .base_url(base)
+            .api_key("key")
+            .retry(RetryPolicy::none())
+            .build()
+            .unwrap()
+    }
+
+    fn retrying_client(base: &str) -> WakatimeClient {
+        WakatimeClient::builder()
+            .base_url(base)
+            .api_key("key")
+            .retry(RetryPolicy {
+                max_retries: 2,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(10),
+            })
+            .requests_per_minute(100)
+            .build()
+            .unwrap()
+    }
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    /// Serves canned HTTP responses, one per connection, and returns the base URL.
+    async fn serve(responses: Vec<(&'static str, &'static str, &'static str)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for (status_line, headers, body) in responses {
+                let (mut sock, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let _ = sock.read(&mut buf).await;
+                let res = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n{}\r\n{}",
+                    status_line,
+                    body.len(),
+                    headers,
+                    body
+                );
+                sock.write_all(res.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}/api/v1", addr)
+    }
+
+    async fn serve_once(
+        status_line: &'static str,
+        headers: &'static str,
+        body: &'static str,
+    ) -> String {
+        serve(vec![(status_line, headers, body)]).await
+    }
+
+    #[test]
+    fn builds_summaries_url() {
+        let c = client("https://example.com/api/v1");
+        let url = c
+            .summaries(ymd(2021, 2, 22), ymd(2021, 2, 23))
+            .project("my project")
+            .url()
+            .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://example.com/api/v1/users/current/summaries?start=2021-02-22&end=2021-02-23&project=my+project"
+        );
+    }
+
+    #[test]
+    fn requires_credentials() {
+        let res = WakatimeClient::builder().build();
+        assert!(matches!(res, Err(WakatimeError::MissingCredentials)));
+    }
+
+    #[test]
+    fn maps_status_codes() {
+        let url = Url::parse(DEFAULT_BASE_URL).unwrap();
+        assert!(WakatimeError::from_status(StatusCode::OK, &url, None, "").is_none());
+        assert!(matches!(
+            WakatimeError::from_status(StatusCode::UNAUTHORIZED, &url, None, ""),
+            Some(WakatimeError::Unauthorized { .. })
+        ));
+        assert!(matches!(
+            WakatimeError::from_status(StatusCode::NOT_FOUND, &url, None, ""),
+            Some(WakatimeError::NotFound { .. })
+        ));
+        assert!(matches!(
+            WakatimeError::from_status(
+                StatusCode::TOO_MANY_REQUESTS,
+                &url,
+                parse_retry_after("30"),
+                ""
+            ),
+            Some(WakatimeError::RateLimited { retry_after: Some(d) }) if d.as_secs() == 30
+        ));
+    }
+
+    #[tokio::test]
+    async fn decodes_from_mock_server() {
+        let base = serve_once(
+            "200 OK",
+            "",
+            r#"{"data": [], "start": "2021-02-22T00:00:00Z", "end": "2021-02-22T23:59:59Z"}"#,
+        )
+        .await;
+        let summaries = client(&base)
+            .summaries(ymd(2021, 2, 22), ymd(2021, 2, 22))
+            .send()
+            .await
+            .unwrap();
+        assert!(summaries.data.is_empty());
+        assert_eq!(summaries.start, "2021-02-22T00:00:00Z");
+    }
+
+    #[tokio::test]
+    async fn decode_error_keeps_body() {
+        let base = serve_once("200 OK", "", "not json").await;
+        let err = client(&base)
+            .summaries(ymd(2021, 2, 22), ymd(2021, 2, 22))
+            .send()
+            .await
+            .unwrap_err();
+        match err {
+            WakatimeError::Decode { body, .. } => assert_eq!(body, "not json"),
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limit_from_mock_server() {
+        let base = serve_once("429 Too Many Requests", "retry-after: 12\r\n", "").await;
+        let err = client(&base)
+            .summaries(ymd(2021, 2, 22), ymd(2021, 2, 22))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, WakatimeError::RateLimited { retry_after: Some(d) } if d.as_secs() == 12)
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let base = serve(vec![
+            ("503 Service Unavailable", "", ""),
+            ("429 Too Many Requests", "retry-after: 0\r\n", ""),
+            (
+                "200 OK",
+                "",
+                r#"{"data": [], "start": "2021-02-22T00:00:00Z", "end": "2021-02-22T23:59:59Z"}"#,
+            ),
+        ])
+        .await;
+        let summaries = retrying_client(&base)
+            .summaries(ymd(2021, 2, 22), ymd(2021, 2, 22))
+            .send()
+            .await
+            .unwrap();
+        assert!(summaries.data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let base = serve(vec![
+            ("500 Internal Server Error", "", "a"),
+            ("502 Bad Gateway", "", "b"),
+            ("503 Service Unavailable", "", "c"),
+        ])
+        .await;
+        let err = retrying_client(&base)
+            .summaries(ymd(2021, 2, 22), ymd(2021, 2, 22))
+            .send()
+            .await
+            .unwrap_err();
+        match err {
+            WakatimeError::Exhausted { attempts, last } => {
+                assert_eq!(attempts, 3);
+                assert!(
+                    matches!(*last, WakatimeError::Status { status, .. } if status.as_u16() == 503)
+                );
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_auth_failure() {
+        let base = serve_once("401 Unauthorized", "", "").await;
+        let err = retrying_client(&base)
+            .summaries(ymd(2021, 2, 22), ymd(2021, 2, 22))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, WakatimeError::Unauthorized { .. }));
+    }
+
+    #[tokio::test]
+    async fn refreshes_rejected_oauth_token() {
+        use crate::oauth::{OAuthApp, OAuthToken};
+
+        let base = serve(vec![
+            ("401 Unauthorized", "", ""),
+            (
+                "200 OK",
+                "",
+                r#"{"access_token": "sec_new", "expires_in": 3600}"#,
+            ),
+            (
+                "200 OK",
+                "",
+                r#"{"data": [], "start": "2021-02-22T00:00:00Z", "end": "2021-02-22T23:59:59Z"}"#,
+            ),
+        ])
+        .await;
+        let source = Arc::new(TokenSource::new(
+            OAuthToken {
+                access_token: "sec_old".into(),
+                refresh_token: Some("ref".into()),
+                expires_at: None,
+                scopes: vec![],
+            },
+            OAuthApp {
+                client_id: "id".into(),
+                client_secret: "secret".into(),
+                token_url: format!("{}/oauth/token", base),
+                redirect_uri: comlib::token_store::redirect_uri(8081),
+            },
+            None,
+            None,
+        ));
+        let client = WakatimeClient::builder()
+            .base_url(&base)
+            .auth(Auth::OAuth(source))
+            .retry(RetryPolicy::none())
+            .build()
+            .unwrap();
+        let summaries = client
+            .summaries(ymd(2021, 2, 22), ymd(2021, 2, 22))
+            .send()
+            .await
+            .unwrap();
+        assert!(summaries.data.is_empty());
+    }
+}
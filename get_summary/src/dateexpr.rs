@@ -0,0 +1,220 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use chrono::{Datelike, Duration, NaiveDate};
+
+/// Inclusive range of days, `first <= last`.
+pub type DateRange = (NaiveDate, NaiveDate);
+
+/// Parses a date expression into the inclusive range of days it denotes.
+///
+/// Accepted forms, relative ones are resolved against `today`:
+///
+/// - `today`, `yesterday`, `N-days-ago`
+/// - `this-week`, `last-week` (ISO weeks, Monday to Sunday)
+/// - `this-month`, `last-month`, `this-year`, `last-year`
+/// - `YYYY-MM-DD`, `YYYY/MM/DD`, `YYYYMMDD`
+/// - `YYYY-MM`, `YYYY/MM` (the whole month), `YYYY` (the whole year)
+pub fn parse_date_expr(expr: &str, today: NaiveDate) -> Result<DateRange, DateExprError> {
+    let s = expr.trim().to_ascii_lowercase();
+    let err = || DateExprError::Invalid {
+        expr: expr.to_string(),
+    };
+    let day = |d: NaiveDate| Ok((d, d));
+
+    match s.as_str() {
+        "today" => return day(today),
+        "yesterday" => return day(today - Duration::days(1)),
+        "this-week" => return Ok(iso_week(today)),
+        "last-week" => return Ok(iso_week(today - Duration::days(7))),
+        "this-month" => return month(today.year(), today.month()).ok_or_else(err),
+        "last-month" => {
+            let (y, m) = if today.month() == 1 {
+                (today.year() - 1, 12)
+            } else {
+                (today.year(), today.month() - 1)
+            };
+            return month(y, m).ok_or_else(err);
+        }
+        "this-year" => return year(today.year()).ok_or_else(err),
+        "last-year" => return year(today.year() - 1).ok_or_else(err),
+        _ => {}
+    }
+
+    if let Some(n) = s.strip_suffix("-days-ago") {
+        // unsigned: "-3-days-ago" would be three days ahead
+        let n: u32 = n.parse().map_err(|_| err())?;
+        let d = today
+            .checked_sub_signed(Duration::days(i64::from(n)))
+            .ok_or_else(err)?;
+        return day(d);
+    }
+    for fmt in &["%Y-%m-%d", "%Y/%m/%d", "%Y%m%d"] {
+        if let Ok(d) = NaiveDate::parse_from_str(&s, fmt) {
+            return day(d);
+        }
+    }
+    let parts: Vec<&str> = s.split(['-', '/']).collect();
+    match parts.as_slice() {
+        [y, m] if y.len() == 4 => {
+            let y: i32 = y.parse().map_err(|_| err())?;
+            let m: u32 = m.parse().map_err(|_| err())?;
+            month(y, m).ok_or_else(err)
+        }
+        [y] if y.len() == 4 => {
+            let y: i32 = y.parse().map_err(|_| err())?;
+            year(y).ok_or_else(err)
+        }
+        _ => Err(err()),
+    }
+}
+
+/// Resolves `--from` / `--to` expressions into one inclusive range.
+///
+/// `from` uses the first day of its expression and `to` the last day of its
+/// own, so `--from 2024-03` alone means the whole of March. A missing side
+/// takes the other side's expression, and both missing means yesterday.
+pub fn resolve_range(
+    from: Option<&str>,
+    to: Option<&str>,
+    today: NaiveDate,
+) -> Result<DateRange, DateExprError> {
+    let (from, to) = match (from, to) {
+        (None, None) => ("yesterday", "yesterday"),
+        (Some(f), None) => (f, f),
+        (None, Some(t)) => (t, t),
+        (Some(f), Some(t)) => (f, t),
+    };
+    let (first, _) = parse_date_expr(from, today)?;
+    let (_, last) = parse_date_expr(to, today)?;
+    if first > last {
+        return Err(DateExprError::Reversed { first, last });
+    }
+    Ok((first, last))
+}
+
+fn iso_week(d: NaiveDate) -> DateRange {
+    let monday = d - Duration::days(d.weekday().num_days_from_monday() as i64);
+    (monday, monday + Duration::days(6))
+}
+
+fn month(y: i32, m: u32) -> Option<DateRange> {
+    let first = NaiveDate::from_ymd_opt(y, m, 1)?;
+    let next = if m == 12 {
+        NaiveDate::from_ymd_opt(y + 1, 1, 1)?
+    } else {
+        NaiveDate::from_ymd_opt(y, m + 1, 1)?
+    };
+    Some((first, next - Duration::days(1)))
+}
+
+fn year(y: i32) -> Option<DateRange> {
+    Some((
+        NaiveDate::from_ymd_opt(y, 1, 1)?,
+        NaiveDate::from_ymd_opt(y, 12, 31)?,
+    ))
+}
+
+/// Error when parsing date expressions
+#[derive(Debug, PartialEq)]
+pub enum DateExprError {
+    /// The expression could not be understood
+    Invalid { expr: String },
+    /// `--from` resolved to a day after `--to`
+    Reversed { first: NaiveDate, last: NaiveDate },
+}
+
+impl Error for DateExprError {}
+
+impl Display for DateExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            DateExprError::Invalid { expr } => write!(
+                f,
+                "invalid date expression [{}], expected e.g. 2024-03-05, 2024-03, 2024, yesterday, last-week or 3-days-ago",
+                expr
+            ),
+            DateExprError::Reversed { first, last } => {
+                write!(f, "start date {} is after end date {}", first, last)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    // a Wednesday
+    fn today() -> NaiveDate {
+        ymd(2024, 3, 13)
+    }
+
+    #[test]
+    fn parses_absolute_dates() {
+        let d = (ymd(2024, 3, 5), ymd(2024, 3, 5));
+        assert_eq!(parse_date_expr("2024-03-05", today()), Ok(d));
+        assert_eq!(parse_date_expr("2024/03/05", today()), Ok(d));
+        assert_eq!(parse_date_expr("20240305", today()), Ok(d));
+        assert_eq!(
+            parse_date_expr("2024-02", today()),
+            Ok((ymd(2024, 2, 1), ymd(2024, 2, 29)))
+        );
+        assert_eq!(
+            parse_date_expr("2023", today()),
+            Ok((ymd(2023, 1, 1), ymd(2023, 12, 31)))
+        );
+    }
+
+    #[test]
+    fn parses_relative_dates() {
+        assert_eq!(
+            parse_date_expr("yesterday", today()),
+            Ok((ymd(2024, 3, 12), ymd(2024, 3, 12)))
+        );
+        assert_eq!(
+            parse_date_expr("last-week", today()),
+            Ok((ymd(2024, 3, 4), ymd(2024, 3, 10)))
+        );
+        assert_eq!(
+            parse_date_expr("last-month", ymd(2024, 1, 10)),
+            Ok((ymd(2023, 12, 1), ymd(2023, 12, 31)))
+        );
+        assert_eq!(
+            parse_date_expr("10-days-ago", today()),
+            Ok((ymd(2024, 3, 3), ymd(2024, 3, 3)))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        assert!(parse_date_expr("2024-13", today()).is_err());
+        assert!(parse_date_expr("2024-02-30", today()).is_err());
+        assert!(parse_date_expr("someday", today()).is_err());
+        assert!(parse_date_expr("", today()).is_err());
+        assert!(parse_date_expr("-3-days-ago", today()).is_err());
+        assert!(parse_date_expr("4000000000-days-ago", today()).is_err());
+        assert!(parse_date_expr("200000000-days-ago", today()).is_err());
+    }
+
+    #[test]
+    fn resolves_ranges() {
+        assert_eq!(
+            resolve_range(None, None, today()),
+            Ok((ymd(2024, 3, 12), ymd(2024, 3, 12)))
+        );
+        assert_eq!(
+            resolve_range(Some("2024-02"), None, today()),
+            Ok((ymd(2024, 2, 1), ymd(2024, 2, 29)))
+        );
+        assert_eq!(
+            resolve_range(Some("2024-01"), Some("last-week"), today()),
+            Ok((ymd(2024, 1, 1), ymd(2024, 3, 10)))
+        );
+        assert!(resolve_range(Some("today"), Some("yesterday"), today()).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::NaiveDate;
+use comlib::{Summaries, SummariesAll};
+use futures::{StreamExt, TryStreamExt};
+
+use crate::client::{WakatimeClient, WakatimeError};
+
+/// Default number of project summaries fetched at the same time.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Errors of [`fetch_summaries_all`], tagged with the request that failed.
+#[derive(Debug)]
+pub enum FetchError {
+    Summaries(WakatimeError),
+    Project { name: String, error: WakatimeError },
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FetchError::Summaries(error) => Some(error),
+            FetchError::Project { error, .. } => Some(error),
+        }
+    }
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            FetchError::Summaries(error) => write!(f, "summaries: {}", error),
+            FetchError::Project { name, error } => {
+                write!(f, "project summaries of {}: {}", name, error)
+            }
+        }
+    }
+}
+
+/// Fetches the summaries of `start..=end` and the per-project summaries of
+/// every project that shows up in them.
+///
+/// At most `concurrency` project requests are in flight at once; they still go
+/// through the client's retry policy and shared request budget.
+pub async fn fetch_summaries_all(
+    client: &WakatimeClient,
+    start: NaiveDate,
+    end: NaiveDate,
+    concurrency: usize,
+) -> Result<SummariesAll, FetchError> {
+    let summaries = client
+        .summaries(start, end)
+        .send()
+        .await
+        .map_err(FetchError::Summaries)?;
+
+    let names = project_names(&summaries);
+    let projects: Vec<(String, Summaries)> = futures::stream::iter(names)
+        .map(|name| async move {
+            match client
+                .summaries(start, end)
+                .project(name.as_str())
+                .send()
+                .await
+            {
+                Ok(proj) => Ok((name, proj)),
+                Err(error) => Err(FetchError::Project { name, error }),
+            }
+        })
+        // buffered (not buffer_unordered) keeps the input order
+        .buffered(concurrency.max(1))
+        .try_collect()
+        .await?;
+
+    Ok(SummariesAll {
+        schema_version: comlib::SCHEMA_VERSION,
+        summaries,
+        projects: projects.into_iter().collect::<BTreeMap<_, _>>(),
+    })
+}
+
+/// Names of every project worked on in any day of `summaries`, sorted.
+fn project_names(summaries: &Summaries) -> BTreeSet<String> {
+    summaries
+        .data
+        .iter()
+        .flat_map(|day| day.projects.iter().map(|p| p.name.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry::RetryPolicy;
+    use comlib::fixtures;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn day(date: &str, projects: &[&str]) -> serde_json::Value {
+        let mut day = fixtures::day(date, 60.0);
+        day["projects"] = projects.iter().map(|p| fixtures::detail(p, 60.0)).collect();
+        day
+    }
+
+    #[test]
+    fn collects_projects_of_every_day() {
+        let summaries: Summaries = serde_json::from_value(serde_json::json!({
+            "data": [day("2021-02-22", &["b", "a"]), day("2021-02-23", &["c", "a"])],
+            "start": "", "end": ""
+        }))
+        .unwrap();
+        let names: Vec<String> = project_names(&summaries).into_iter().collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+    }
+
+    /// Answers every request after a delay, the project summaries with the
+    /// project name as `start`, and counts the requests in flight.
+    async fn serve_slowly(
+        projects: &'static [&'static str],
+        max_in_flight: Arc<AtomicUsize>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(async move {
+            loop {
+                let (mut sock, _) = listener.accept().await.unwrap();
+                let (in_flight, max_in_flight) = (in_flight.clone(), max_in_flight.clone());
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    let n = sock.read(&mut buf).await.unwrap();
+                    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(now, Ordering::SeqCst);
+
+                    let project = projects
+                        .iter()
+                        .position(|p| request.contains(&format!("project={} ", p)));
+                    // later projects answer first
+                    let delay = project.map(|i| projects.len() - i).unwrap_or(0);
+                    tokio::time::sleep(Duration::from_millis(20 * delay as u64)).await;
+                    let body = match project {
+                        Some(i) => serde_json::json!({
+                            "data": [day("2021-02-22", &[]), day("2021-02-23", &[])],
+                            "start": projects[i], "end": ""
+                        }),
+                        None => serde_json::json!({
+                            "data": [day("2021-02-22", &projects[..2]), day("2021-02-23", &projects[2..])],
+                            "start": "", "end": ""
+                        }),
+                    }
+                    .to_string();
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    let res = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    sock.write_all(res.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        format!("http://{}/api/v1", addr)
+    }
+
+    #[tokio::test]
+    async fn bounds_requests_in_flight_and_keeps_the_days_in_order() {
+        const PROJECTS: &[&str] = &["a", "b", "c", "d", "e", "f"];
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let base = serve_slowly(PROJECTS, max_in_flight.clone()).await;
+        let client = WakatimeClient::builder()
+            .base_url(base)
+            .api_key("key")
+            .retry(RetryPolicy::none())
+            .build()
+            .unwrap();
+        let ymd = |d| NaiveDate::from_ymd_opt(2021, 2, d).unwrap();
+
+        let all = fetch_summaries_all(&client, ymd(22), ymd(23), 2)
+            .await
+            .unwrap();
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+        let dates: Vec<&str> = all
+            .summaries
+            .data
+            .iter()
+            .map(|d| d.range.date.as_str())
+            .collect();
+        assert_eq!(dates, vec!["2021-02-22", "2021-02-23"]);
+        assert_eq!(all.projects.len(), PROJECTS.len());
+        for (name, project) in &all.projects {
+            assert_eq!(&project.start, name);
+            let dates: Vec<&str> = project.data.iter().map(|d| d.range.date.as_str()).collect();
+            assert_eq!(dates, vec!["2021-02-22", "2021-02-23"]);
+        }
+    }
+}
@@ -1 +1,8 @@
+pub mod client;
+pub mod dateexpr;
+pub mod fetch;
+pub mod oauth;
+pub mod retry;
+pub mod sink;
+pub mod sync;
 pub mod url_encode;
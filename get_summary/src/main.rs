@@ -1,15 +1,27 @@
-use std::{collections::HashMap, io::Write};
+use std::{
+    collections::BTreeSet,
+    convert::TryFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::Result;
-use chrono::{Local, TimeZone};
+use chrono::{Local, NaiveDate};
+use clap::{Args, Parser, Subcommand};
+use comdb::Database;
+use comlib::validate::{Report, DEFAULT_TOLERANCE};
 use comlib::*;
 use config::Config;
-use futures::executor::block_on;
-use get_summary::url_encode::encode;
+use get_summary::client::{Auth, WakatimeClient};
+use get_summary::dateexpr::{parse_date_expr, resolve_range, DateRange};
+use get_summary::fetch::{fetch_summaries_all, DEFAULT_CONCURRENCY};
+use get_summary::oauth::{OAuthApp, OAuthToken, TokenSource, DEFAULT_TOKEN_URL};
+use get_summary::retry::RetryPolicy;
+use get_summary::sink::{DatabaseSink, JsonFileSink, StdoutFormat, StdoutSink, SummarySink};
+use get_summary::sync::{
+    archive_dates, archive_range, CoverageReport, SyncPlan, DEFAULT_REVISE_DAYS,
+};
 use once_cell::sync::Lazy;
-use reqwest::{self, StatusCode};
-use rust_decimal::{Decimal, prelude::FromPrimitive};
-use tokio_postgres::{Client, Error as PgError, NoTls};
 use serde_json::json;
 
 static SETTINGS: Lazy<Config> = Lazy::new(|| {
@@ -20,252 +32,619 @@ static SETTINGS: Lazy<Config> = Lazy::new(|| {
     settings
 });
 
-async fn request_wakatime(
-    start: &str,
-    end: &str,
-    proj_name: Option<&str>,
-) -> anyhow::Result<Summaries> {
-    // get the key from Settings.toml
-    let sct_api_key = SETTINGS.get_str("secret-api-key")?;
-    let client = reqwest::Client::new();
-
-    let mut req_url = format!(
-        "https://wakatime.com/api/v1/users/current/summaries?start={}&end={}",
-        encode(start),
-        encode(end)
-    );
-    if let Some(pjnm) = proj_name {
-        req_url = format!("{}&project={}", req_url, encode(pjnm));
+/// Builds the API client from Settings.toml.
+fn build_client() -> anyhow::Result<WakatimeClient> {
+    let api_key = SETTINGS.get_str("secret-api-key").ok();
+    let mut builder = WakatimeClient::builder();
+    builder = match oauth_source(api_key.clone())? {
+        Some(source) => builder.auth(Auth::OAuth(Arc::new(source))),
+        None => builder.api_key(SETTINGS.get_str("secret-api-key")?),
+    };
+    // optional, e.g. a self-hosted WakaTime compatible server
+    if let Ok(base_url) = SETTINGS.get_str("api-base-url") {
+        builder = builder.base_url(base_url);
+    }
+    if let Ok(max_retries) = SETTINGS.get_int("retry-max") {
+        builder = builder.retry(RetryPolicy {
+            max_retries: max_retries.max(0) as u32,
+            ..RetryPolicy::default()
+        });
     }
-    let res = client
-        .get(&req_url)
-        .header(
-            "Authorization",
-            format!("Basic {}", base64::encode(sct_api_key)),
-        )
-        .send()
-        .await?;
-    if res.status() != StatusCode::OK {
-        return Err(anyhow::anyhow!(format!(
-            "bad request => status code: {}",
-            res.status()
-        )));
+    if let Ok(limit) = SETTINGS.get_int("requests-per-minute") {
+        builder = builder.requests_per_minute(limit.max(1) as u32);
     }
-    let body = res.text().await?;
-    let summaries: Summaries = serde_json::from_str(&body)?;
+    Ok(builder.build()?)
+}
+
+/// OAuth token source when `token-file` is configured, `api_key` is used once
+/// the token can no longer be refreshed.
+fn oauth_source(api_key: Option<String>) -> anyhow::Result<Option<TokenSource>> {
+    let path = match SETTINGS.get_str("token-file") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => return Ok(None),
+    };
+    let token = match OAuthToken::load(&path) {
+        Ok(token) => token,
+        Err(e) if api_key.is_some() => {
+            println!("{}, using the API key", e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // the redirect URI get_auth issued the token with, checked on refresh
+    let port = match SETTINGS.get_int("oauth-port") {
+        Ok(port) => u16::try_from(port)
+            .map_err(|_| anyhow::anyhow!("oauth-port {} is not a port number", port))?,
+        Err(_) => token_store::DEFAULT_OAUTH_PORT,
+    };
+    let app = OAuthApp {
+        client_id: SETTINGS.get_str("api-id").unwrap_or_default(),
+        client_secret: SETTINGS.get_str("api-secret").unwrap_or_default(),
+        token_url: SETTINGS
+            .get_str("oauth-token-url")
+            .unwrap_or_else(|_| DEFAULT_TOKEN_URL.to_string()),
+        redirect_uri: token_store::redirect_uri(port),
+    };
+    Ok(Some(TokenSource::new(token, app, Some(path), api_key)))
+}
 
-    Ok(summaries)
+async fn connect_db() -> Result<Database> {
+    Ok(comdb::connect(&SETTINGS.get_str("db_url")?).await?)
 }
 
-async fn update_log(
-    client: &Client,
-    log_dt: &chrono::NaiveDate,
-    editors: &serde_json::Value,
-    langs: &serde_json::Value,
-    machines: &serde_json::Value,
-    projects: &serde_json::Value,
-    depends: &serde_json::Value,
-    grand_total_sec: &Decimal,
-) -> Result<u64, PgError> {
-    let stmt = client
-        .prepare(r#"
-UPDATE wakatime_summary SET
-    editors=$2,
-    langs=$3,
-    machine=$4,
-    projects=$5,
-    depends=$6,
-    grand_total_sec=$7
-where date=$1"#)
-        .await
-        .unwrap();
-    client.execute(&stmt, &[
-        &log_dt,
-        &editors,
-        &langs,
-        &machines,
-        &projects,
-        &depends,
-        &grand_total_sec
-    ]).await
+#[derive(Debug, Parser)]
+#[clap(
+    name = "get_summary",
+    about = "Fetches WakaTime summaries into JSON files and the database"
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Fetch a range of days, yesterday when no range is given
+    Fetch(FetchArgs),
+    /// Fetch only the days not stored yet since `sync-start`, plus the last few days
+    Sync(SyncArgs),
+    /// Register res_*.json archive files to the database
+    Import(ImportArgs),
+    /// Write the days registered in the database as JSON
+    Export(ExportArgs),
+    /// Refill the wakatime_fact and wakatime_rollup tables from the stored JSON documents
+    Rebuild(RebuildArgs),
+    /// Check that the res_*.json archive files add up to their grand_total
+    Verify(VerifyArgs),
+}
+
+/// Date range options. Both ends are inclusive.
+#[derive(Debug, Args)]
+struct RangeOpts {
+    /// Whole range as one expression, e.g. yesterday, last-week, 2024-03, 2024
+    #[clap(conflicts_with_all = &["from", "to"])]
+    when: Option<String>,
+    /// First day (inclusive), e.g. 2024-03-01, 2024/03/01, 3-days-ago
+    #[clap(long)]
+    from: Option<String>,
+    /// Last day (inclusive), defaults to the end of --from
+    #[clap(long)]
+    to: Option<String>,
+}
+
+impl RangeOpts {
+    fn resolve(&self) -> Result<DateRange> {
+        let today = Local::now().naive_local().date();
+        let range = match &self.when {
+            Some(when) => parse_date_expr(when, today)?,
+            None => resolve_range(self.from.as_deref(), self.to.as_deref(), today)?,
+        };
+        Ok(range)
+    }
 }
 
-async fn register_wakatime(log_dt: &chrono::NaiveDate, summary: &SummariesAll) -> Result<()> {
-    let db_url = SETTINGS.get_str("db_url")?;
-    //println!("db_url: {}, date: {}", db_url, date);
-    let (client, connection) = tokio_postgres::connect(&db_url, NoTls).await?;
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            println!("connection error: {}", e);
+/// Where fetched days are stored.
+#[derive(Debug, Args)]
+struct StoreOpts {
+    /// Directory the res_YYYYMMDD.json files are written to
+    #[clap(long, default_value = ".")]
+    output_dir: PathBuf,
+    /// Where to store the days: file, postgres, sqlite, stdout or stdout-json.
+    /// Repeat to combine (Settings: sinks, default file and postgres)
+    #[clap(long = "sink", possible_values = SINK_NAMES)]
+    sinks: Vec<String>,
+    /// Do not write JSON files
+    #[clap(long)]
+    no_file: bool,
+    /// Do not register to the database
+    #[clap(long)]
+    no_db: bool,
+    /// Only print what would be fetched
+    #[clap(long)]
+    dry_run: bool,
+    /// Project summaries fetched at the same time (Settings: concurrency)
+    #[clap(long)]
+    concurrency: Option<usize>,
+}
+
+impl StoreOpts {
+    fn concurrency(&self) -> usize {
+        match self.concurrency {
+            Some(n) => n.max(1),
+            None => match SETTINGS.get_int("concurrency") {
+                Ok(n) => n.max(1) as usize,
+                Err(_) => DEFAULT_CONCURRENCY,
+            },
         }
-    });
-
-    let json = serde_json::to_string(summary)?;
-    let jval: serde_json::Value = serde_json::from_str(&json).unwrap();
-    let null_obj = json!({});
-    let null_arr = json!([{}]);
-    let summary_data = jval.get("summaries")
-        .unwrap_or(&null_obj)
-        .get("data")
-        .unwrap_or(&null_arr)
-        .get(0)
-        .unwrap_or(&null_obj);
-    let depends = summary_data
-        .get("dependencies")
-        .unwrap_or(&null_arr);
-    let editors = summary_data
-        .get("editors")
-        .unwrap_or(&null_arr);
-    let langs = summary_data
-        .get("languages")
-        .unwrap_or(&null_arr);
-    let machines = summary_data
-        .get("machines")
-        .unwrap_or(&null_arr);
-    let projects = summary_data
-        .get("projects")
-        .unwrap_or(&null_arr);
-    
-    let grand_total_sec = match summary_data.get("grand_total") {
-        Some(j) => {
-            match j.get("total_seconds") {
-                Some(v) => v.as_f64().unwrap_or(0.0),
-                None => 0.0,
+    }
+}
+
+#[derive(Debug, Args)]
+struct FetchArgs {
+    #[clap(flatten)]
+    range: RangeOpts,
+    /// Send one request per day (and project) instead of one for the whole range
+    #[clap(long)]
+    per_day: bool,
+    #[clap(flatten)]
+    store: StoreOpts,
+}
+
+#[derive(Debug, Args)]
+struct SyncArgs {
+    /// First day to keep complete (Settings: sync-start)
+    #[clap(long)]
+    start: Option<String>,
+    /// Recent days fetched again on every run (Settings: sync-revise-days)
+    #[clap(long)]
+    revise_days: Option<i64>,
+    /// Where to look up the days already stored (Settings: sync-source)
+    #[clap(long, possible_values = &["db", "files"])]
+    source: Option<String>,
+    #[clap(flatten)]
+    store: StoreOpts,
+}
+
+#[derive(Debug, Args)]
+struct ImportArgs {
+    /// Directory containing the res_*.json files
+    #[clap(default_value = ".")]
+    dir: PathBuf,
+    /// Only print what would be imported
+    #[clap(long)]
+    dry_run: bool,
+    /// Days written per transaction
+    #[clap(long, default_value_t = comdb::DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
+}
+
+#[derive(Debug, Args)]
+struct RebuildArgs {
+    /// Days read from the database at a time
+    #[clap(long, default_value_t = comdb::DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
+}
+
+#[derive(Debug, Args)]
+struct VerifyArgs {
+    /// Directory containing the res_*.json files
+    #[clap(default_value = ".")]
+    dir: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct ExportArgs {
+    #[clap(flatten)]
+    range: RangeOpts,
+    /// Output file, stdout when omitted
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+const SINK_NAMES: &[&str] = &["file", "db", "postgres", "sqlite", "stdout", "stdout-json"];
+
+impl StoreOpts {
+    /// Opens every configured sink, failing before anything is fetched when
+    /// one of them can not be used.
+    async fn open_sinks(&self) -> Result<Vec<Box<dyn SummarySink>>> {
+        let names = if !self.sinks.is_empty() {
+            self.sinks.clone()
+        } else {
+            match SETTINGS.get_array("sinks") {
+                Ok(values) => values
+                    .into_iter()
+                    .map(|v| v.into_str())
+                    .collect::<Result<Vec<_>, _>>()?,
+                Err(_) => vec!["file".into(), "db".into()],
             }
-        }
-        None => 0.0,
-    };
+        };
 
-    let stmt = client
-        .prepare(r#"
-INSERT INTO wakatime_summary
-    (date, editors, langs, machine, projects, depends, grand_total_sec)
-VALUES
-    ($1, $2, $3, $4, $5, $6, $7)"#)
-        .await?;
-    let grand_total_sec = Decimal::from_f64(grand_total_sec).unwrap();
-    let _ = client
-        .execute(&stmt, &[
-            &log_dt,
-            &editors,
-            &langs,
-            &machines,
-            &projects,
-            &depends,
-            &grand_total_sec
-        ])
-        .await
-        .unwrap_or_else(|_| block_on(update_log(
-            &client,
-            &log_dt,
-            &editors,
-            &langs,
-            &machines,
-            &projects,
-            &depends,
-            &grand_total_sec)).unwrap());
+        let mut sinks: Vec<Box<dyn SummarySink>> = Vec::new();
+        for name in names {
+            let sink: Box<dyn SummarySink> = match name.as_str() {
+                "file" if self.no_file => continue,
+                "db" | "postgres" | "sqlite" if self.no_db => continue,
+                "file" => Box::new(JsonFileSink::new(&self.output_dir)),
+                // postgres is the name from before db_url could point to SQLite
+                "db" | "postgres" => {
+                    Box::new(DatabaseSink::connect(&name, &SETTINGS.get_str("db_url")?).await?)
+                }
+                "sqlite" => {
+                    let path = SETTINGS
+                        .get_str("sqlite-path")
+                        .unwrap_or_else(|_| "wakatime.db".into());
+                    let db_url = format!("sqlite://{}", path);
+                    Box::new(DatabaseSink::connect(&name, &db_url).await?)
+                }
+                "stdout" => Box::new(StdoutSink::new(StdoutFormat::Human)),
+                "stdout-json" => Box::new(StdoutSink::new(StdoutFormat::Json)),
+                other => {
+                    anyhow::bail!("unknown sink [{}], expected one of {:?}", other, SINK_NAMES)
+                }
+            };
+            sinks.push(sink);
+        }
+        if sinks.is_empty() {
+            anyhow::bail!("no sink left to store the summaries to");
+        }
+        Ok(sinks)
+    }
+}
 
+/// Stores `start..=end` into every sink, reporting each failing sink.
+async fn store_summary(
+    start: NaiveDate,
+    end: NaiveDate,
+    summary_all: &SummariesAll,
+    sinks: &[Box<dyn SummarySink>],
+) -> anyhow::Result<()> {
+    let mut failed = Vec::new();
+    for sink in sinks {
+        if let Err(err) = sink.store(start, end, summary_all).await {
+            println!("{} error! {} - {} : {}", sink.name(), start, end, err);
+            failed.push(sink.name().to_string());
+        }
+    }
+    if !failed.is_empty() {
+        anyhow::bail!("could not store to {}", failed.join(", "));
+    }
     Ok(())
 }
 
 async fn get_onedate_summary(
-    dt_start: &chrono::DateTime<Local>,
-    dt_end: &chrono::DateTime<Local>,
-    save_file: bool,
+    client: &WakatimeClient,
+    date: NaiveDate,
+    store: &StoreOpts,
+    sinks: &[Box<dyn SummarySink>],
 ) -> anyhow::Result<()> {
-    let summary = request_wakatime(
-        &dt_start.format("%Y-%m-%d").to_string(),
-        &dt_end.format("%Y-%m-%d").to_string(),
-        None,
-    )
-    .await?;
-    let mut proj_summary: HashMap<String, Summaries> = HashMap::new();
-    for dat in &summary.data[0].projects {
-        let proj = request_wakatime(
-            &dt_start.format("%Y-%m-%d").to_string(),
-            &dt_end.format("%Y-%m-%d").to_string(),
-            Some(&dat.name),
-        )
-        .await?;
-        proj_summary.insert(dat.name.clone(), proj);
-    }
+    let summary_all = fetch_summaries_all(client, date, date, store.concurrency()).await?;
+    store_summary(date, date, &summary_all, sinks).await
+}
 
-    let summary_all = SummariesAll {
-        summaries: summary,
-        projects: proj_summary,
-    };
+/// Days of one range request, each stored on its own.
+#[derive(Default)]
+struct RangeOutcome {
+    stored: Vec<NaiveDate>,
+    /// Days a sink failed for, the other days are stored anyway
+    failed: Vec<(NaiveDate, anyhow::Error)>,
+}
 
-    if dt_start.format("%Y%m%d").to_string() == dt_end.format("%Y%m%d").to_string() {
-        println!("db regist start.");
-        let dt_start = dt_start.naive_local().date();
-        match register_wakatime(&dt_start, &summary_all).await {
-            Ok(_) => {}
+/// Fetches `start..=end` with one summaries request plus one request per
+/// project, then stores every day on its own. Fails only when the range
+/// could not be fetched.
+async fn get_range_summary(
+    client: &WakatimeClient,
+    start: NaiveDate,
+    end: NaiveDate,
+    store: &StoreOpts,
+    sinks: &[Box<dyn SummarySink>],
+) -> anyhow::Result<RangeOutcome> {
+    let summary_all = fetch_summaries_all(client, start, end, store.concurrency()).await?;
+    let mut outcome = RangeOutcome::default();
+    for (date, day) in summary_all.split_by_day()? {
+        match store_summary(date, date, &day, sinks).await {
+            Ok(()) => {
+                println!("process => {}", date);
+                outcome.stored.push(date);
+            }
             Err(err) => {
-                println!("db regist error! : {:?}", err);
+                println!("process failed => {}: {:#}", date, err);
+                outcome.failed.push((date, err));
             }
         }
     }
+    Ok(outcome)
+}
 
-    if save_file {
-        let str_dt_start = dt_start.format("%Y%m%d").to_string();
-        let str_dt_end = dt_end.format("%Y%m%d").to_string();
-        let file_name: String;
-        if str_dt_start == str_dt_end {
-            file_name = format!("res_{}.json", str_dt_start);
+/// Dates already registered in the DB from `from` on.
+async fn stored_dates_db(from: NaiveDate) -> Result<BTreeSet<NaiveDate>> {
+    Ok(connect_db().await?.stored_dates(from).await?)
+}
+
+async fn fetch(args: FetchArgs) -> Result<()> {
+    let (first, last) = args.range.resolve()?;
+    let days = (last - first).num_days() + 1;
+    if args.store.dry_run {
+        let mode = if args.per_day {
+            "one request per day"
         } else {
-            file_name = format!("res_{}-{}.json", str_dt_start, str_dt_end);
-        }
-        let mut f = std::fs::File::create(file_name)?;
-        let body_txt = serde_json::to_string_pretty(&summary_all)?;
-        if let Err(e) = f.write(body_txt.as_bytes()) {
-            println!("error! : {:?}", e);
+            "one range request"
+        };
+        println!("would fetch {} - {} ({} days, {})", first, last, days, mode);
+        return Ok(());
+    }
+    let client = build_client()?;
+    let sinks = args.store.open_sinks().await?;
+
+    // a failed day must not abort a long backfill, report them all at the end
+    // with the number of days each failure lost
+    let mut failures: Vec<(String, i64, anyhow::Error)> = Vec::new();
+    if args.per_day {
+        let mut date = first;
+        while date <= last {
+            match get_onedate_summary(&client, date, &args.store, &sinks).await {
+                Ok(_) => println!("process => {}", date),
+                Err(err) => {
+                    println!("process failed => {}: {:#}", date, err);
+                    failures.push((date.to_string(), 1, err));
+                }
+            }
+            date += chrono::Duration::days(1);
         }
     } else {
-        println!("error! : {:?}", summary_all);
+        match get_range_summary(&client, first, last, &args.store, &sinks).await {
+            Ok(outcome) => failures.extend(
+                outcome
+                    .failed
+                    .into_iter()
+                    .map(|(date, err)| (date.to_string(), 1, err)),
+            ),
+            Err(err) => {
+                println!("process failed => {} - {}: {:#}", first, last, err);
+                failures.push((format!("{} - {}", first, last), days, err));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("failed:");
+        for (dt, _, err) in &failures {
+            println!("  {} : {:#}", dt, err);
+        }
+        let failed_days: i64 = failures.iter().map(|(_, days, _)| days).sum();
+        return Err(anyhow::anyhow!(
+            "{} day(s) could not be fetched or stored",
+            failed_days
+        ));
     }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let mut dt_end = chrono::Local::now();
-    dt_end = dt_end - chrono::Duration::days(1);
-    let mut dt_start = dt_end;
-    let mut save_file = true;
-
-    let args: Vec<String> = std::env::args().skip(1).collect();
-    if args.len() >= 1 {
-        if let Ok(tmp_dt_0) =
-            chrono::NaiveDateTime::parse_from_str(
-                &format!("{} 00:00", args[0]),
-                "%Y/%m/%d %H:%M")
-        {
-            dt_start = Local.from_local_datetime(&tmp_dt_0).unwrap();
+/// Fetches every day between `sync-start` and yesterday that is not stored yet,
+/// plus the last `sync-revise-days` days, and prints a coverage report.
+async fn sync(args: SyncArgs) -> Result<()> {
+    let today = Local::now().naive_local().date();
+    let start = match &args.start {
+        Some(expr) => parse_date_expr(expr, today)?.0,
+        None => parse_date_expr(&SETTINGS.get_str("sync-start")?, today)?.0,
+    };
+    let end = today - chrono::Duration::days(1);
+    let revise_days = match args.revise_days {
+        Some(n) => n,
+        None => SETTINGS
+            .get_int("sync-revise-days")
+            .unwrap_or(DEFAULT_REVISE_DAYS),
+    };
+    let source = match &args.source {
+        Some(source) => source.clone(),
+        None => SETTINGS
+            .get_str("sync-source")
+            .unwrap_or_else(|_| "db".into()),
+    };
+    // the fetched days have to end up where the next run looks for them
+    match source.as_str() {
+        "files" if args.store.no_file => {
+            anyhow::bail!("--no-file with sync-source files would fetch the same days on every run")
+        }
+        "files" => {}
+        _ if args.store.no_db => {
+            anyhow::bail!("--no-db with sync-source db would fetch the same days on every run")
+        }
+        _ => {}
+    }
+    let stored = match source.as_str() {
+        "files" => archive_dates(&args.store.output_dir)?,
+        _ => stored_dates_db(start).await?,
+    };
+
+    let plan = SyncPlan::new(start, end, &stored, revise_days);
+    let mut report = CoverageReport::new(start, end, &plan);
+    if args.store.dry_run {
+        for (first, last) in plan.runs() {
+            println!("would fetch {} - {}", first, last);
+        }
+        print!("{}", report);
+        return Ok(());
+    }
+
+    let client = build_client()?;
+    let sinks = args.store.open_sinks().await?;
+    for (first, last) in plan.runs() {
+        match get_range_summary(&client, first, last, &args.store, &sinks).await {
+            Ok(outcome) => {
+                report.filled.extend(outcome.stored);
+                for (date, err) in outcome.failed {
+                    report.failed.push(((date, date), format!("{:#}", err)));
+                }
+            }
+            Err(err) => {
+                println!("process failed => {} - {}: {:#}", first, last, err);
+                report.failed.push(((first, last), format!("{:#}", err)));
+            }
+        }
+    }
+
+    print!("{}", report);
+    if !report.failed.is_empty() {
+        let failed_days: i64 = report
+            .failed
+            .iter()
+            .map(|((first, last), _)| (*last - *first).num_days() + 1)
+            .sum();
+        return Err(anyhow::anyhow!(
+            "{} day(s) could not be fetched or stored",
+            failed_days
+        ));
+    }
+    Ok(())
+}
+
+/// Archive files directly inside `dir`, sorted by name.
+fn archive_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_archive = path
+            .file_name()
+            .map(|name| archive_range(&name.to_string_lossy()).is_some())
+            .unwrap_or(false);
+        if is_archive {
+            files.push(path);
         }
-        if args.len() >= 2 {
-            if let Ok(tmp_dt_1) = chrono::NaiveDateTime::parse_from_str(
-                &format!("{} 00:00", &args[1]),
-                "%Y/%m/%d %H:%M",
-            ) {
-                dt_end = Local.from_local_datetime(&tmp_dt_1).unwrap();
+    }
+    files.sort();
+    Ok(files)
+}
+
+async fn import(args: ImportArgs) -> Result<()> {
+    let mut failures: Vec<(PathBuf, anyhow::Error)> = Vec::new();
+    let mut rows = Vec::new();
+    for path in archive_files(&args.dir)? {
+        let days = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(serde_json::from_str::<SummariesAll>(&json)?))
+            .and_then(|all| Ok(comdb::SummaryRow::from_summaries(&all)?));
+        match days {
+            Ok(days) => {
+                for row in &days {
+                    println!("read {} from {}", row.date, path.display());
+                }
+                rows.extend(days);
+            }
+            Err(err) => {
+                println!("skip {} : {:#}", path.display(), err);
+                failures.push((path, err));
             }
         }
     }
-    for a in args {
-        save_file = a == "save_file";
+    // the readable files are stored, but a script has to notice the rest
+    let outcome = |skipped: usize, failed: usize| -> Result<()> {
+        if skipped > 0 || failed > 0 {
+            return Err(anyhow::anyhow!(
+                "{} file(s) skipped, {} day(s) failed",
+                skipped,
+                failed
+            ));
+        }
+        Ok(())
+    };
+    if args.dry_run {
+        println!(
+            "{} day(s) would be imported, {} file(s) skipped",
+            rows.len(),
+            failures.len()
+        );
+        return outcome(failures.len(), 0);
     }
 
-    let mut loop_cnt = 1;
-    loop {
-        let one_dt_end = dt_start + chrono::Duration::days(loop_cnt);
-        get_onedate_summary(&one_dt_end, &one_dt_end, save_file).await?;
+    let mut db = connect_db().await?;
+    let report = db.upsert_rows(&rows, args.batch_size).await?;
+    for (date, reason) in &report.failed {
+        println!("failed {} : {}", date, reason);
+    }
+    println!("{}, {} file(s) skipped", report, failures.len());
+    outcome(failures.len(), report.failed.len())
+}
+
+async fn export(args: ExportArgs) -> Result<()> {
+    let (first, last) = args.range.resolve()?;
+    let rows = connect_db().await?.stored_rows(first, last).await?;
+    let days: Vec<serde_json::Value> = rows
+        .iter()
+        .map(|row| {
+            json!({
+                "date": row.date.format("%Y-%m-%d").to_string(),
+                "editors": row.editors,
+                "languages": row.langs,
+                "machines": row.machines,
+                "projects": row.projects,
+                "dependencies": row.depends,
+                "categories": row.categories,
+                "operating_systems": row.operating_systems,
+                "range": row.range,
+                "grand_total_seconds": row.grand_total_sec.as_f64(),
+            })
+        })
+        .collect();
+    let body_txt = serde_json::to_string_pretty(&days)?;
+    match args.output {
+        Some(path) => std::fs::write(path, body_txt)?,
+        None => println!("{}", body_txt),
+    }
+    Ok(())
+}
 
-        println!("process => {}", one_dt_end);
-        if one_dt_end > dt_end {
-            break;
+fn verify(args: VerifyArgs) -> Result<()> {
+    let mut report = Report::default();
+    for path in archive_files(&args.dir)? {
+        let all = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(serde_json::from_str::<SummariesAll>(&json)?));
+        let all = match all {
+            Ok(all) => all,
+            Err(err) => {
+                println!("skip {} : {:#}", path.display(), err);
+                report.skipped += 1;
+                continue;
+            }
+        };
+        report.days += all.summaries.data.len();
+        for finding in comlib::validate(&all, DEFAULT_TOLERANCE) {
+            println!("{} : {}", path.display(), finding);
+            report.findings += 1;
         }
-        loop_cnt += 1;
     }
+    println!("{}", report);
+    if !report.passed() {
+        return Err(anyhow::anyhow!("verification failed"));
+    }
+    Ok(())
+}
 
+async fn rebuild(args: RebuildArgs) -> Result<()> {
+    let mut db = connect_db().await?;
+    let days = db.rebuild_facts(args.batch_size).await?;
+    println!("wakatime_fact rebuilt from {} day(s)", days);
+    let periods = db.rebuild_rollups().await?;
+    println!("wakatime_rollup rebuilt with {} period(s)", periods);
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // no subcommand: fetch yesterday with the default options
+    let command = match Cli::parse().command {
+        Some(command) => command,
+        None => Cli::parse_from(["get_summary", "fetch"])
+            .command
+            .expect("fetch is a subcommand"),
+    };
+    match command {
+        Command::Fetch(args) => fetch(args).await,
+        Command::Sync(args) => sync(args).await,
+        Command::Import(args) => import(args).await,
+        Command::Export(args) => export(args).await,
+        Command::Rebuild(args) => rebuild(args).await,
+        Command::Verify(args) => verify(args),
+    }
+}
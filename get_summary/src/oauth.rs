@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use chrono::Utc;
+use comlib::token_store::{self, RefreshError};
+use reqwest::{header, StatusCode};
+use tokio::sync::Mutex;
+
+use crate::client::WakatimeError;
+pub use comlib::token_store::{OAuthApp, OAuthToken, DEFAULT_TOKEN_URL};
+
+/// Hands out a valid access token, refreshing and persisting it when it expires.
+///
+/// When the token can not be refreshed and an API key is available, every
+/// later request uses the API key instead.
+#[derive(Debug)]
+pub struct TokenSource {
+    state: Mutex<TokenState>,
+    app: OAuthApp,
+    path: Option<PathBuf>,
+    fallback_api_key: Option<String>,
+}
+
+#[derive(Debug)]
+struct TokenState {
+    token: OAuthToken,
+    use_fallback: bool,
+}
+
+impl TokenSource {
+    /// `path` is where refreshed tokens are written back to.
+    pub fn new(
+        token: OAuthToken,
+        app: OAuthApp,
+        path: Option<PathBuf>,
+        fallback_api_key: Option<String>,
+    ) -> TokenSource {
+        TokenSource {
+            state: Mutex::new(TokenState {
+                token,
+                use_fallback: false,
+            }),
+            app,
+            path,
+            fallback_api_key,
+        }
+    }
+
+    /// Value of the `Authorization` header for the next request.
+    pub(crate) async fn header_value(
+        &self,
+        http: &reqwest::Client,
+    ) -> Result<String, WakatimeError> {
+        let mut state = self.state.lock().await;
+        if !state.use_fallback && state.token.is_expired(Utc::now()) {
+            match self.refresh(http, &state.token).await {
+                Ok(token) => {
+                    if let Some(path) = &self.path {
+                        if let Err(e) = token.save(path) {
+                            println!("token save error! : {:?}", e);
+                        }
+                    }
+                    state.token = token;
+                }
+                Err(err) => match &self.fallback_api_key {
+                    Some(_) => {
+                        println!("token refresh failed, falling back to the API key: {}", err);
+                        state.use_fallback = true;
+                    }
+                    None => return Err(err),
+                },
+            }
+        }
+        match (&self.fallback_api_key, state.use_fallback) {
+            (Some(key), true) => Ok(format!("Basic {}", base64::encode(key))),
+            _ => Ok(format!("Bearer {}", state.token.access_token)),
+        }
+    }
+
+    /// Forces a refresh before the next request, e.g. after a 401.
+    /// Returns false when a refresh can not help.
+    pub(crate) async fn invalidate(&self) -> bool {
+        let mut state = self.state.lock().await;
+        if state.use_fallback {
+            return false;
+        }
+        state.token.expires_at = Some(Utc::now());
+        true
+    }
+
+    async fn refresh(
+        &self,
+        http: &reqwest::Client,
+        token: &OAuthToken,
+    ) -> Result<OAuthToken, WakatimeError> {
+        let post = |url: String, form: Vec<(&'static str, String)>| async move {
+            let res = http
+                .post(&url)
+                .header(header::ACCEPT, "application/json")
+                .form(&form)
+                .send()
+                .await?;
+            let status = res.status().as_u16();
+            Ok((status, res.text().await?))
+        };
+        token_store::refresh(&self.app, token, post)
+            .await
+            .map_err(|err| match err {
+                RefreshError::NoRefreshToken => WakatimeError::TokenRefresh {
+                    status: None,
+                    body: err.to_string(),
+                },
+                RefreshError::Send(err) => WakatimeError::Http(err),
+                RefreshError::Rejected { status, body } => WakatimeError::TokenRefresh {
+                    status: StatusCode::from_u16(status).ok(),
+                    body,
+                },
+            })
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+/// How often and how long to wait before a failed request is sent again.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, `0` disables retrying
+    pub max_retries: u32,
+    /// Upper bound of the first backoff
+    pub base_delay: Duration,
+    /// Upper bound of any backoff, including `Retry-After`
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Exponential backoff with full jitter for the given retry (0-based).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let cap = self.ceiling(retry);
+        if cap.is_zero() {
+            return cap;
+        }
+        let millis = rand::thread_rng().gen_range(0..=cap.as_millis() as u64);
+        Duration::from_millis(millis)
+    }
+
+    /// Delay to honour a `Retry-After` header, clamped to `max_delay`.
+    pub fn retry_after(&self, wait: Duration) -> Duration {
+        wait.min(self.max_delay)
+    }
+
+    fn ceiling(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.min(16));
+        self.base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+/// Request budget shared by every clone of a client.
+///
+/// Allows at most `per_minute` requests in any sliding 60 second window, and
+/// lets a 429 pause every caller at once instead of each one finding out on
+/// its own.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: usize,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    sent: VecDeque<Instant>,
+    paused_until: Option<Instant>,
+}
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+impl LimiterState {
+    /// Reserves a slot at `now`, or returns the instant to try again at.
+    fn reserve(&mut self, now: Instant, per_minute: usize) -> Option<Instant> {
+        if let Some(until) = self.paused_until {
+            if until > now {
+                return Some(until);
+            }
+            self.paused_until = None;
+        }
+        while let Some(first) = self.sent.front() {
+            if now.duration_since(*first) >= WINDOW {
+                self.sent.pop_front();
+            } else {
+                break;
+            }
+        }
+        if self.sent.len() >= per_minute {
+            return self.sent.front().map(|first| *first + WINDOW);
+        }
+        self.sent.push_back(now);
+        None
+    }
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        RateLimiter {
+            per_minute: per_minute.max(1) as usize,
+            state: Mutex::new(LimiterState::default()),
+        }
+    }
+
+    /// Waits until a request may be sent and counts it against the budget.
+    pub async fn acquire(&self) {
+        loop {
+            let wait_until = self
+                .state
+                .lock()
+                .unwrap()
+                .reserve(Instant::now(), self.per_minute);
+            match wait_until {
+                None => return,
+                Some(until) => tokio::time::sleep_until(until.into()).await,
+            }
+        }
+    }
+
+    /// Holds back every caller for `wait`, e.g. after a 429.
+    pub fn pause_for(&self, wait: Duration) {
+        let until = Instant::now() + wait;
+        let mut state = self.state.lock().unwrap();
+        if state.paused_until.is_none_or(|cur| cur < until) {
+            state.paused_until = Some(until);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_is_bounded() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+        };
+        assert_eq!(policy.ceiling(0), Duration::from_millis(100));
+        assert_eq!(policy.ceiling(3), Duration::from_millis(800));
+        assert_eq!(policy.ceiling(10), Duration::from_secs(2));
+        assert_eq!(policy.ceiling(100), Duration::from_secs(2));
+        for retry in 0..10 {
+            assert!(policy.backoff(retry) <= policy.ceiling(retry));
+        }
+        assert_eq!(
+            policy.retry_after(Duration::from_secs(600)),
+            Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn limiter_enforces_window() {
+        let mut state = LimiterState::default();
+        let t0 = Instant::now();
+        assert_eq!(state.reserve(t0, 2), None);
+        assert_eq!(state.reserve(t0 + Duration::from_secs(1), 2), None);
+        assert_eq!(
+            state.reserve(t0 + Duration::from_secs(2), 2),
+            Some(t0 + WINDOW)
+        );
+        assert_eq!(state.reserve(t0 + WINDOW, 2), None);
+    }
+
+    #[test]
+    fn limiter_pause_blocks_everyone() {
+        let mut state = LimiterState::default();
+        let t0 = Instant::now();
+        state.paused_until = Some(t0 + Duration::from_secs(5));
+        assert_eq!(state.reserve(t0, 100), Some(t0 + Duration::from_secs(5)));
+        assert_eq!(state.reserve(t0 + Duration::from_secs(5), 100), None);
+    }
+}
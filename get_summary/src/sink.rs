@@ -0,0 +1,321 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use comdb::SummaryRow;
+use comlib::{Aggregate, SummariesAll};
+
+/// Destination fetched summaries are written to.
+///
+/// A run stores every fetched unit into each configured sink in turn, so one
+/// failing sink does not keep the others from being written.
+#[async_trait]
+pub trait SummarySink: Send + Sync {
+    /// Name used when reporting errors, e.g. `file` or `postgres`.
+    fn name(&self) -> &str;
+
+    /// Stores the summaries of `start..=end`.
+    async fn store(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+        summary: &SummariesAll,
+    ) -> Result<(), SinkError>;
+}
+
+/// Name of the archive file holding `start..=end`, e.g. `res_20210222.json`.
+pub fn archive_file_name(start: NaiveDate, end: NaiveDate) -> String {
+    let str_dt_start = start.format("%Y%m%d").to_string();
+    let str_dt_end = end.format("%Y%m%d").to_string();
+    if str_dt_start == str_dt_end {
+        format!("res_{}.json", str_dt_start)
+    } else {
+        format!("res_{}-{}.json", str_dt_start, str_dt_end)
+    }
+}
+
+/// Writes each unit to `res_*.json` in a directory.
+pub struct JsonFileSink {
+    dir: PathBuf,
+}
+
+impl JsonFileSink {
+    pub fn new(dir: impl Into<PathBuf>) -> JsonFileSink {
+        JsonFileSink { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl SummarySink for JsonFileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn store(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+        summary: &SummariesAll,
+    ) -> Result<(), SinkError> {
+        let body_txt = serde_json::to_string_pretty(summary)?;
+        let path = self.dir.join(archive_file_name(start, end));
+        tokio::fs::write(path, body_txt).await?;
+        Ok(())
+    }
+}
+
+/// Registers each day to the `wakatime_summary` table of a PostgreSQL or
+/// SQLite database, replacing the stored day if there is one.
+pub struct DatabaseSink {
+    name: String,
+    db: tokio::sync::Mutex<comdb::Database>,
+}
+
+impl DatabaseSink {
+    /// Connects and migrates the schema when it is not up to date. `name` is
+    /// the sink name used in reports.
+    pub async fn connect(name: &str, db_url: &str) -> Result<DatabaseSink, SinkError> {
+        let db = comdb::connect(db_url).await?;
+        Ok(DatabaseSink {
+            name: name.to_string(),
+            db: tokio::sync::Mutex::new(db),
+        })
+    }
+}
+
+#[async_trait]
+impl SummarySink for DatabaseSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn store(
+        &self,
+        _start: NaiveDate,
+        _end: NaiveDate,
+        summary: &SummariesAll,
+    ) -> Result<(), SinkError> {
+        let rows = SummaryRow::from_summaries(summary)?;
+        let mut db = self.db.lock().await;
+        let report = db.upsert_rows(&rows, comdb::DEFAULT_BATCH_SIZE).await?;
+        match report.failed.is_empty() {
+            true => Ok(()),
+            false => Err(SinkError::Rejected(report.failed)),
+        }
+    }
+}
+
+/// Output format of [`StdoutSink`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StdoutFormat {
+    /// One line per day with the total and the projects, and a line with
+    /// the totals of a unit of several days
+    Human,
+    /// The whole unit as JSON, as written to the archive files
+    Json,
+}
+
+/// Prints each unit, e.g. to pipe a fetch into another tool.
+pub struct StdoutSink {
+    format: StdoutFormat,
+}
+
+impl StdoutSink {
+    pub fn new(format: StdoutFormat) -> StdoutSink {
+        StdoutSink { format }
+    }
+}
+
+#[async_trait]
+impl SummarySink for StdoutSink {
+    fn name(&self) -> &str {
+        match self.format {
+            StdoutFormat::Human => "stdout",
+            StdoutFormat::Json => "stdout-json",
+        }
+    }
+
+    async fn store(
+        &self,
+        _start: NaiveDate,
+        _end: NaiveDate,
+        summary: &SummariesAll,
+    ) -> Result<(), SinkError> {
+        match self.format {
+            StdoutFormat::Json => println!("{}", serde_json::to_string(summary)?),
+            StdoutFormat::Human => {
+                for day in &summary.summaries.data {
+                    println!("{}", human_line(day));
+                }
+                if summary.summaries.data.len() > 1 {
+                    println!("{}", total_line(summary));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn human_line(day: &comlib::SummariesData) -> String {
+    let projects: Vec<String> = day
+        .projects
+        .iter()
+        .map(|p| format!("{} {}", p.name, p.total_seconds.hm()))
+        .collect();
+    let mut line = format!(
+        "{}  {:>6}",
+        day.range.date,
+        day.grand_total.total_seconds.hm()
+    );
+    if !projects.is_empty() {
+        line.push_str("  ");
+        line.push_str(&projects.join(", "));
+    }
+    line
+}
+
+/// Projects shown in the total line, the others are summed as "Other"
+const TOTAL_TOP_PROJECTS: usize = 5;
+
+fn total_line(summary: &SummariesAll) -> String {
+    let total = Aggregate::from_summaries(vec![summary]);
+    let projects: Vec<String> = total
+        .totals("projects")
+        .top(TOTAL_TOP_PROJECTS, "Other")
+        .iter()
+        .map(|p| format!("{} {}", p.name, p.seconds.hm()))
+        .collect();
+    let mut line = format!("{:<10}  {:>6}", "total", total.grand_total.hm());
+    if !projects.is_empty() {
+        line.push_str("  ");
+        line.push_str(&projects.join(", "));
+    }
+    line
+}
+
+/// Error of a [`SummarySink`]
+#[derive(Debug)]
+pub enum SinkError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Db(comdb::DbError),
+    /// Days the database refused to store, with the reason
+    Rejected(Vec<(NaiveDate, String)>),
+}
+
+impl Error for SinkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SinkError::Io(error) => Some(error),
+            SinkError::Json(error) => Some(error),
+            SinkError::Db(error) => Some(error),
+            SinkError::Rejected(_) => None,
+        }
+    }
+}
+
+impl Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            SinkError::Io(error) => write!(f, "io error: {}", error),
+            SinkError::Json(error) => write!(f, "json error: {}", error),
+            SinkError::Db(error) => write!(f, "{}", error),
+            SinkError::Rejected(days) => {
+                write!(f, "rejected")?;
+                for (i, (date, reason)) in days.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{} ({})", sep, date, reason)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for SinkError {
+    fn from(error: std::io::Error) -> Self {
+        SinkError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SinkError {
+    fn from(error: serde_json::Error) -> Self {
+        SinkError::Json(error)
+    }
+}
+
+impl From<comdb::DbError> for SinkError {
+    fn from(error: comdb::DbError) -> Self {
+        SinkError::Db(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comlib::fixtures;
+    use serde_json::{json, Value};
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn summary(dates: &[&str]) -> SummariesAll {
+        let days: Vec<Value> = dates
+            .iter()
+            .map(|date| {
+                let mut day = fixtures::day(date, 3900.0);
+                day["editors"] = json!([fixtures::detail("vim", 3900.0)]);
+                day["projects"] = json!([fixtures::detail("crate", 3900.0)]);
+                day
+            })
+            .collect();
+        serde_json::from_value(fixtures::summaries_all(days)).unwrap()
+    }
+
+    #[test]
+    fn names_archive_files() {
+        assert_eq!(
+            archive_file_name(ymd(2021, 2, 22), ymd(2021, 2, 22)),
+            "res_20210222.json"
+        );
+        assert_eq!(
+            archive_file_name(ymd(2021, 2, 1), ymd(2021, 2, 28)),
+            "res_20210201-20210228.json"
+        );
+    }
+
+    #[test]
+    fn formats_human_line() {
+        let s = summary(&["2021-02-22"]);
+        assert_eq!(
+            human_line(&s.summaries.data[0]),
+            "2021-02-22    1:05  crate 1:05"
+        );
+    }
+
+    #[test]
+    fn formats_total_line() {
+        let s = summary(&["2021-02-22", "2021-02-23"]);
+        assert_eq!(total_line(&s), "total         2:10  crate 2:10");
+    }
+
+    #[tokio::test]
+    async fn sqlite_replaces_stored_day() {
+        let sink = DatabaseSink::connect("sqlite", "sqlite::memory:")
+            .await
+            .unwrap();
+        let day = ymd(2021, 2, 22);
+        sink.store(day, day, &summary(&["2021-02-22"]))
+            .await
+            .unwrap();
+        sink.store(day, day, &summary(&["2021-02-22"]))
+            .await
+            .unwrap();
+        let db = sink.db.lock().await;
+        let stored = db.stored_dates(day).await.unwrap();
+        assert_eq!(stored.len(), 1);
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use chrono::{Duration, NaiveDate};
+
+/// Days WakaTime may still revise after the fact, re-fetched on every sync.
+pub const DEFAULT_REVISE_DAYS: i64 = 3;
+
+/// What a sync run has to fetch.
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncPlan {
+    /// Days between start and end that are not stored yet
+    pub missing: Vec<NaiveDate>,
+    /// Stored days that are fetched again because they may have been revised
+    pub revised: Vec<NaiveDate>,
+}
+
+impl SyncPlan {
+    /// Builds the plan for `start..=end` given the days already stored.
+    pub fn new(
+        start: NaiveDate,
+        end: NaiveDate,
+        stored: &BTreeSet<NaiveDate>,
+        revise_days: i64,
+    ) -> SyncPlan {
+        // more revise days than the span covers revise all of it
+        let span = (end - start).num_days() + 1;
+        let revise_from = end - Duration::days(revise_days.clamp(0, span.max(0)) - 1);
+        let mut plan = SyncPlan::default();
+        let mut day = start;
+        while day <= end {
+            if !stored.contains(&day) {
+                plan.missing.push(day);
+            } else if day >= revise_from {
+                plan.revised.push(day);
+            }
+            day += Duration::days(1);
+        }
+        plan
+    }
+
+    /// Contiguous `(first, last)` ranges covering the missing and revised days,
+    /// each one can be fetched with a single range request.
+    pub fn runs(&self) -> Vec<(NaiveDate, NaiveDate)> {
+        let days: BTreeSet<NaiveDate> = self
+            .missing
+            .iter()
+            .chain(self.revised.iter())
+            .copied()
+            .collect();
+        contiguous_runs(&days)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.revised.is_empty()
+    }
+}
+
+/// Groups sorted days into inclusive `(first, last)` runs without gaps.
+pub fn contiguous_runs(days: &BTreeSet<NaiveDate>) -> Vec<(NaiveDate, NaiveDate)> {
+    let mut runs: Vec<(NaiveDate, NaiveDate)> = Vec::new();
+    for day in days {
+        match runs.last_mut() {
+            Some((_, last)) if *last + Duration::days(1) == *day => *last = *day,
+            _ => runs.push((*day, *day)),
+        }
+    }
+    runs
+}
+
+/// Dates covered by the `prefix_YYYYMMDD.json` and `prefix_YYYYMMDD-YYYYMMDD.json`
+/// files directly inside `dir`.
+pub fn archive_dates(dir: &Path) -> std::io::Result<BTreeSet<NaiveDate>> {
+    let mut dates = BTreeSet::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if let Some((first, last)) = archive_range(&name.to_string_lossy()) {
+            let mut day = first;
+            while day <= last {
+                dates.insert(day);
+                day += Duration::days(1);
+            }
+        }
+    }
+    Ok(dates)
+}
+
+/// Parses the date range out of an archive file name such as `res_20210222.json`.
+pub fn archive_range(file_name: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let stem = file_name.strip_suffix(".json")?;
+    let dates = stem.rsplit('_').next()?;
+    let parse = |s: &str| NaiveDate::parse_from_str(s, "%Y%m%d").ok();
+    match dates.split_once('-') {
+        Some((first, last)) => Some((parse(first)?, parse(last)?)),
+        None => parse(dates).map(|d| (d, d)),
+    }
+}
+
+/// Outcome of a sync run, printed at the end.
+#[derive(Debug, Default)]
+pub struct CoverageReport {
+    pub start: Option<NaiveDate>,
+    pub end: Option<NaiveDate>,
+    pub gaps: Vec<(NaiveDate, NaiveDate)>,
+    pub revised: usize,
+    pub filled: BTreeSet<NaiveDate>,
+    pub failed: Vec<((NaiveDate, NaiveDate), String)>,
+}
+
+impl CoverageReport {
+    pub fn new(start: NaiveDate, end: NaiveDate, plan: &SyncPlan) -> CoverageReport {
+        CoverageReport {
+            start: Some(start),
+            end: Some(end),
+            gaps: contiguous_runs(&plan.missing.iter().copied().collect()),
+            revised: plan.revised.len(),
+            ..CoverageReport::default()
+        }
+    }
+
+    /// Missing days that are still missing after the run.
+    pub fn remaining(&self) -> Vec<(NaiveDate, NaiveDate)> {
+        let mut left = BTreeSet::new();
+        for (first, last) in &self.gaps {
+            let mut day = *first;
+            while day <= *last {
+                if !self.filled.contains(&day) {
+                    left.insert(day);
+                }
+                day += Duration::days(1);
+            }
+        }
+        contiguous_runs(&left)
+    }
+}
+
+fn fmt_run(f: &mut fmt::Formatter, (first, last): &(NaiveDate, NaiveDate)) -> fmt::Result {
+    if first == last {
+        write!(f, "{}", first)
+    } else {
+        write!(
+            f,
+            "{} - {} ({} days)",
+            first,
+            last,
+            (*last - *first).num_days() + 1
+        )
+    }
+}
+
+impl Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            writeln!(f, "coverage {} - {}", start, end)?;
+        }
+        let missing: i64 = self
+            .gaps
+            .iter()
+            .map(|(first, last)| (*last - *first).num_days() + 1)
+            .sum();
+        writeln!(f, "  gaps found : {} ({} days)", self.gaps.len(), missing)?;
+        for run in &self.gaps {
+            write!(f, "    ")?;
+            fmt_run(f, run)?;
+            writeln!(f)?;
+        }
+        writeln!(f, "  re-fetched : {} days", self.revised)?;
+        writeln!(f, "  stored     : {} days", self.filled.len())?;
+        let remaining = self.remaining();
+        writeln!(f, "  remaining  : {}", remaining.len())?;
+        for run in &remaining {
+            write!(f, "    ")?;
+            fmt_run(f, run)?;
+            writeln!(f)?;
+        }
+        for (run, err) in &self.failed {
+            write!(f, "  failed     : ")?;
+            fmt_run(f, run)?;
+            writeln!(f, " => {}", err)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn plans_missing_and_revised_days() {
+        let stored: BTreeSet<NaiveDate> = vec![
+            ymd(2021, 3, 1),
+            ymd(2021, 3, 2),
+            ymd(2021, 3, 5),
+            ymd(2021, 3, 6),
+            ymd(2021, 3, 7),
+        ]
+        .into_iter()
+        .collect();
+        let plan = SyncPlan::new(ymd(2021, 3, 1), ymd(2021, 3, 7), &stored, 2);
+        assert_eq!(plan.missing, vec![ymd(2021, 3, 3), ymd(2021, 3, 4)]);
+        assert_eq!(plan.revised, vec![ymd(2021, 3, 6), ymd(2021, 3, 7)]);
+        assert_eq!(
+            plan.runs(),
+            vec![
+                (ymd(2021, 3, 3), ymd(2021, 3, 4)),
+                (ymd(2021, 3, 6), ymd(2021, 3, 7))
+            ]
+        );
+    }
+
+    #[test]
+    fn nothing_to_do_when_complete() {
+        let stored: BTreeSet<NaiveDate> =
+            vec![ymd(2021, 3, 1), ymd(2021, 3, 2)].into_iter().collect();
+        let plan = SyncPlan::new(ymd(2021, 3, 1), ymd(2021, 3, 2), &stored, 0);
+        assert!(plan.is_empty());
+        assert!(plan.runs().is_empty());
+    }
+
+    #[test]
+    fn revises_at_most_the_whole_span() {
+        let stored: BTreeSet<NaiveDate> =
+            vec![ymd(2021, 3, 1), ymd(2021, 3, 2)].into_iter().collect();
+        let plan = SyncPlan::new(ymd(2021, 3, 1), ymd(2021, 3, 2), &stored, i64::MAX);
+        assert_eq!(plan.revised, vec![ymd(2021, 3, 1), ymd(2021, 3, 2)]);
+    }
+
+    #[test]
+    fn parses_archive_names() {
+        assert_eq!(
+            archive_range("res_20210222.json"),
+            Some((ymd(2021, 2, 22), ymd(2021, 2, 22)))
+        );
+        assert_eq!(
+            archive_range("res_20210101-20210131.json"),
+            Some((ymd(2021, 1, 1), ymd(2021, 1, 31)))
+        );
+        assert_eq!(archive_range("Settings.toml"), None);
+        assert_eq!(archive_range("res_latest.json"), None);
+    }
+
+    #[test]
+    fn report_remaining_gaps() {
+        let plan = SyncPlan {
+            missing: vec![ymd(2021, 3, 3), ymd(2021, 3, 4), ymd(2021, 3, 9)],
+            revised: vec![],
+        };
+        let mut report = CoverageReport::new(ymd(2021, 3, 1), ymd(2021, 3, 10), &plan);
+        report.filled.insert(ymd(2021, 3, 3));
+        report.filled.insert(ymd(2021, 3, 4));
+        assert_eq!(report.remaining(), vec![(ymd(2021, 3, 9), ymd(2021, 3, 9))]);
+    }
+}
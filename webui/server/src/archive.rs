@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::NaiveDate;
+use comlib::{Aggregate, SummariesAll, SummariesData};
+
+use crate::RankingItem;
+
+/// One archive file as it was when last read.
+struct ArchiveFile {
+    modified: SystemTime,
+    len: u64,
+    /// Summary of the whole day for each day in the file, empty when the
+    /// file could not be read
+    days: Vec<(NaiveDate, SummariesData)>,
+}
+
+/// Files of a directory of `res_*.json` written by get_summary, re-read when
+/// their modification time or size changes.
+pub struct ArchiveScanner {
+    dir: PathBuf,
+    files: HashMap<PathBuf, ArchiveFile>,
+}
+
+/// Days of an archive directory in memory, answering the rankings without a
+/// database.
+#[derive(Default)]
+pub struct ArchiveIndex {
+    /// Summary per day; when several files hold a day the newest file wins
+    days: BTreeMap<NaiveDate, SummariesData>,
+}
+
+impl ArchiveScanner {
+    pub fn new(dir: impl Into<PathBuf>) -> ArchiveScanner {
+        ArchiveScanner {
+            dir: dir.into(),
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Reads the files added or changed since the last call and forgets the
+    /// removed ones. Returns the new index when anything changed.
+    pub fn refresh(&mut self) -> std::io::Result<Option<ArchiveIndex>> {
+        let mut seen = HashSet::new();
+        let mut changed = false;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !is_archive(&path) {
+                continue;
+            }
+            let meta = match std::fs::metadata(&path) {
+                Ok(meta) => meta,
+                // removed while scanning, the next refresh drops it
+                Err(_) => continue,
+            };
+            let modified = meta.modified()?;
+            seen.insert(path.clone());
+            let unchanged = self
+                .files
+                .get(&path)
+                .map(|f| f.modified == modified && f.len == meta.len())
+                .unwrap_or(false);
+            if unchanged {
+                continue;
+            }
+            let days = match read_days(&path) {
+                Ok(days) => days,
+                Err(err) => {
+                    println!("skip {} : {:#}", path.display(), err);
+                    Vec::new()
+                }
+            };
+            self.files.insert(
+                path,
+                ArchiveFile {
+                    modified,
+                    len: meta.len(),
+                    days,
+                },
+            );
+            changed = true;
+        }
+        let before = self.files.len();
+        self.files.retain(|path, _| seen.contains(path));
+        changed |= self.files.len() != before;
+
+        if !changed {
+            return Ok(None);
+        }
+        let mut files: Vec<&ArchiveFile> = self.files.values().collect();
+        files.sort_by_key(|f| f.modified);
+        let days = files
+            .into_iter()
+            .flat_map(|f| f.days.iter().cloned())
+            .collect();
+        Ok(Some(ArchiveIndex { days }))
+    }
+}
+
+impl ArchiveIndex {
+    /// Number of days indexed.
+    pub fn len(&self) -> usize {
+        self.days.len()
+    }
+
+    /// Same result as the database rankings, see `FACT_RANKING`.
+    pub fn ranking(
+        &self,
+        dimension: &str,
+        exclude: Option<&str>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Vec<RankingItem> {
+        Aggregate::from_days(self.days.range(from..=to).map(|(_, day)| day))
+            .totals(dimension)
+            .ranked()
+            .into_iter()
+            .filter(|share| Some(share.name.as_str()) != exclude)
+            .map(|share| RankingItem::new(share.name, share.seconds))
+            .collect()
+    }
+}
+
+fn is_archive(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    name.starts_with("res_") && name.ends_with(".json")
+}
+
+/// Every day in the file, without the per-project breakdown.
+fn read_days(path: &Path) -> anyhow::Result<Vec<(NaiveDate, SummariesData)>> {
+    let all: SummariesAll = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    all.summaries
+        .data
+        .into_iter()
+        .map(|day| Ok((day.range.parsed_date()?, day)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comlib::fixtures;
+    use serde_json::json;
+
+    fn write_day(dir: &Path, date: &str, editor: &str, seconds: f64) {
+        let mut day = fixtures::day(date, seconds);
+        day["editors"] = json!([fixtures::detail(editor, seconds)]);
+        let doc = fixtures::summaries_all(vec![day]);
+        let name = format!("res_{}.json", date.replace('-', ""));
+        std::fs::write(dir.join(name), doc.to_string()).unwrap();
+    }
+
+    #[test]
+    fn follows_changes_in_the_directory() {
+        let dir = std::env::temp_dir().join(format!("archive-index-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_day(&dir, "2021-02-22", "vim", 3600.0);
+        write_day(&dir, "2021-02-23", "vim", 1800.0);
+        std::fs::write(dir.join("res_broken.json"), "{").unwrap();
+
+        let mut scanner = ArchiveScanner::new(&dir);
+        let index = scanner.refresh().unwrap().unwrap();
+        assert_eq!(index.len(), 2);
+        let day = |d| NaiveDate::from_ymd_opt(2021, 2, d).unwrap();
+        let rank = index.ranking("editors", None, day(22), day(28));
+        assert_eq!((rank[0].title.as_str(), rank[0].hours), ("vim", 1.5));
+        assert!(scanner.refresh().unwrap().is_none());
+
+        std::fs::remove_file(dir.join("res_20210223.json")).unwrap();
+        write_day(&dir, "2021-02-24", "emacs", 7200.0);
+        let index = scanner.refresh().unwrap().unwrap();
+        let rank = index.ranking("editors", Some("vim"), day(22), day(28));
+        assert_eq!(rank.len(), 1);
+        assert_eq!((rank[0].title.as_str(), rank[0].hours), ("emacs", 2.0));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
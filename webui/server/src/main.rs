@@ -6,9 +6,23 @@ use rocket::{
     response::content::Custom,
     Config, Response, State,
 };
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use sqlx::{postgres::PgPoolOptions, sqlite::SqlitePoolOptions, Pool, Postgres, Sqlite};
 
-use std::{borrow::Cow, collections::HashMap, env, path::PathBuf};
+use archive::{ArchiveIndex, ArchiveScanner};
+use chrono::NaiveDate;
+use comdb::Period;
+use comlib::Seconds;
+
+mod archive;
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    env,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 use serde_json;
@@ -29,94 +43,222 @@ static ASSET_FILES: Lazy<HashMap<Cow<str>, Option<()>>> = Lazy::new(|| {
     files
 });
 
-#[derive(Debug, Deserialize, Serialize, sqlx::FromRow)]
+#[derive(Debug, Deserialize, Serialize)]
 struct RankingItem {
     title: String,
     hours: f64,
 }
 
 impl RankingItem {
+    fn new(title: String, seconds: Seconds) -> RankingItem {
+        RankingItem {
+            title,
+            hours: seconds.hours(),
+        }
+    }
+}
+
+/// Seconds per name summed over the days, `$4` is a name left out.
+const FACT_RANKING: &str = r#"
+select name, sum(seconds) seconds
+from wakatime_fact
+where dimension = $3 and project = ''
+    and date >= $1::date and date <= $2::date
+    and ($4::text is null or name <> $4)
+group by name
+order by seconds desc
+"#;
+
+/// Same as `FACT_RANKING` over the rollups of the period `$5`.
+const ROLLUP_RANKING: &str = r#"
+select name, sum(seconds) seconds
+from wakatime_rollup
+where period = $5 and dimension = $3 and project = ''
+    and period_start >= $1::date and period_end <= $2::date
+    and ($4::text is null or name <> $4)
+group by name
+order by seconds desc
+"#;
+
+/// `FACT_RANKING` for SQLite, where dates are `YYYY-MM-DD` text.
+const SQLITE_FACT_RANKING: &str = r#"
+select name, sum(seconds) seconds
+from wakatime_fact
+where dimension = ?3 and project = ''
+    and date >= ?1 and date <= ?2
+    and (?4 is null or name <> ?4)
+group by name
+order by seconds desc
+"#;
+
+/// `ROLLUP_RANKING` for SQLite.
+const SQLITE_ROLLUP_RANKING: &str = r#"
+select name, sum(seconds) seconds
+from wakatime_rollup
+where period = ?5 and dimension = ?3 and project = ''
+    and period_start >= ?1 and period_end <= ?2
+    and (?4 is null or name <> ?4)
+group by name
+order by seconds desc
+"#;
+
+/// Pool of the database `DATABASE_URL` points to.
+enum DbPool {
+    Postgres(Pool<Postgres>),
+    /// `sqlite://...`, a file written by get_summary or bulk_regist
+    Sqlite(Pool<Sqlite>),
+}
+
+impl DbPool {
+    async fn connect(database_url: &str) -> Result<DbPool, sqlx::Error> {
+        Ok(match comdb::sqlite_path(database_url) {
+            Some(_) => DbPool::Sqlite(
+                SqlitePoolOptions::new()
+                    .max_connections(5)
+                    .connect(database_url)
+                    .await?,
+            ),
+            None => DbPool::Postgres(
+                PgPoolOptions::new()
+                    .max_connections(5)
+                    .connect(database_url)
+                    .await?,
+            ),
+        })
+    }
+
+    /// Version of the migrated schema, `None` when it was never migrated.
+    async fn schema_version(&self) -> Option<i32> {
+        let version = match self {
+            DbPool::Postgres(pool) => {
+                sqlx::query_scalar(comdb::SCHEMA_VERSION_QUERY)
+                    .fetch_one(pool)
+                    .await
+            }
+            DbPool::Sqlite(pool) => {
+                sqlx::query_scalar(comdb::SCHEMA_VERSION_QUERY)
+                    .fetch_one(pool)
+                    .await
+            }
+        };
+        version.unwrap_or(None)
+    }
+}
+
+fn parse_date(s: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y%m%d")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y-%m-%d"))
+        .ok()
+}
+
+impl DbPool {
+    /// Ranking of `dimension` in `from..=to`, read from the coarsest rollup
+    /// that covers the range exactly and from the daily facts otherwise.
+    async fn ranking(
+        &self,
+        dimension: &str,
+        exclude: Option<&str>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<RankingItem>, sqlx::Error> {
+        let period = Period::covering(from, to);
+        let (from, to) = (from.to_string(), to.to_string());
+        let rows = match self {
+            DbPool::Postgres(pool) => {
+                let sql = match period {
+                    Some(_) => ROLLUP_RANKING,
+                    None => FACT_RANKING,
+                };
+                let mut query = sqlx::query_as::<_, (String, f64)>(sql)
+                    .bind(from)
+                    .bind(to)
+                    .bind(dimension)
+                    .bind(exclude);
+                if let Some(period) = period {
+                    query = query.bind(period.as_str());
+                }
+                query.fetch_all(pool).await?
+            }
+            DbPool::Sqlite(pool) => {
+                let sql = match period {
+                    Some(_) => SQLITE_ROLLUP_RANKING,
+                    None => SQLITE_FACT_RANKING,
+                };
+                let mut query = sqlx::query_as::<_, (String, f64)>(sql)
+                    .bind(from)
+                    .bind(to)
+                    .bind(dimension)
+                    .bind(exclude);
+                if let Some(period) = period {
+                    query = query.bind(period.as_str());
+                }
+                query.fetch_all(pool).await?
+            }
+        };
+        Ok(rows
+            .into_iter()
+            .map(|(title, seconds)| RankingItem::new(title, Seconds(seconds)))
+            .collect())
+    }
+}
+
+/// Where the rankings are read from.
+enum Source {
+    Db(DbPool),
+    /// `ARCHIVE_DIR`, the res_*.json files of get_summary kept in memory
+    Archive(Arc<RwLock<ArchiveIndex>>),
+}
+
+impl RankingItem {
+    async fn get_ranking(
+        dimension: &str,
+        exclude: Option<&str>,
+        from: &str,
+        to: &str,
+        source: &Source,
+    ) -> anyhow::Result<Vec<RankingItem>> {
+        let (from, to) = match (parse_date(from), parse_date(to)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => anyhow::bail!("invalid date range {} - {}", from, to),
+        };
+        let rank = match source {
+            Source::Db(pool) => pool.ranking(dimension, exclude, from, to).await?,
+            Source::Archive(index) => index
+                .read()
+                .expect("archive index poisoned")
+                .ranking(dimension, exclude, from, to),
+        };
+        Ok(rank)
+    }
+
     pub async fn get_editors_ranking(
         from: &str,
         to: &str,
-        pool: &Pool<Postgres>,
-    ) -> anyhow::Result<Vec<RankingItem>, sqlx::Error> {
-        let res = sqlx::query_as::<_, RankingItem>(
-            r#"
-select title, (sum(total_seconds) / 3600) hours from
-    (select
-        jsonb_path_query(w.data, '$.summaries.data.editors.name')#>>'{}' title,
-        jsonb_path_query(w.data, '$.summaries.data.editors.total_seconds')::double precision total_seconds
-    from wakatime_dat w
-    where w.date >= $1 and w.date <= $2
-    ) x
-group by title
-order by hours desc
-                "#,
-        )
-        .bind(from)
-        .bind(to)
-        .fetch_all(&*pool)
-        .await;
-        res
+        source: &Source,
+    ) -> anyhow::Result<Vec<RankingItem>> {
+        Self::get_ranking("editors", None, from, to, source).await
     }
 
     pub async fn get_lang_ranking(
         from: &str,
         to: &str,
-        pool: &Pool<Postgres>,
-    ) -> anyhow::Result<Vec<RankingItem>, sqlx::Error> {
-        let res = sqlx::query_as::<_, RankingItem>(
-            r#"
-select title, (sum(total_seconds) / 3600) hours from
-    (select
-        jsonb_path_query(w.data, '$.summaries.data.languages.name')#>>'{}' title,
-        jsonb_path_query(w.data, '$.summaries.data.languages.total_seconds')::double precision total_seconds
-    from wakatime_dat w
-    where w.date >= $1 and w.date <= $2
-    ) x
-where title <> 'Other'
-group by title
-order by hours desc
-                "#,
-        )
-        .bind(from)
-        .bind(to)
-        .fetch_all(&*pool)
-        .await;
-        res
+        source: &Source,
+    ) -> anyhow::Result<Vec<RankingItem>> {
+        Self::get_ranking("languages", Some("Other"), from, to, source).await
     }
 
     pub async fn get_projects_ranking(
         from: &str,
         to: &str,
-        pool: &Pool<Postgres>,
-    ) -> anyhow::Result<Vec<RankingItem>, sqlx::Error> {
-        let res = sqlx::query_as::<_, RankingItem>(
-            r#"
-select title, (sum(total_seconds) / 3600) hours from
-    (select
-        jsonb_path_query(w.data, '$.summaries.data.projects.name')#>>'{}' title,
-        jsonb_path_query(w.data, '$.summaries.data.projects.total_seconds')::double precision total_seconds
-    from wakatime_dat w
-    where w.date >= $1 and w.date <= $2
-    ) x
-where title <> 'Unknown Project'
-group by title
-order by hours desc
-                "#,
-        )
-        .bind(from)
-        .bind(to)
-        .fetch_all(&*pool)
-        .await;
-        res
+        source: &Source,
+    ) -> anyhow::Result<Vec<RankingItem>> {
+        Self::get_ranking("projects", Some("Unknown Project"), from, to, source).await
     }
 }
 
 #[get("/editors/<from>/<to>")]
-async fn editors(pool: &State<Pool<Postgres>>, from: &str, to: &str) -> Result<String, Status> {
-    let rank = RankingItem::get_editors_ranking(from, to, &pool).await;
+async fn editors(source: &State<Source>, from: &str, to: &str) -> Result<String, Status> {
+    let rank = RankingItem::get_editors_ranking(from, to, source).await;
     match rank {
         Ok(rank) => Ok(serde_json::to_string(&rank).unwrap()),
         _ => Err(Status::NotFound),
@@ -124,8 +266,8 @@ async fn editors(pool: &State<Pool<Postgres>>, from: &str, to: &str) -> Result<S
 }
 
 #[get("/langs/<from>/<to>")]
-async fn langs(pool: &State<Pool<Postgres>>, from: &str, to: &str) -> Result<String, Status> {
-    let rank = RankingItem::get_lang_ranking(from, to, &pool).await;
+async fn langs(source: &State<Source>, from: &str, to: &str) -> Result<String, Status> {
+    let rank = RankingItem::get_lang_ranking(from, to, source).await;
     match rank {
         Ok(rank) => Ok(serde_json::to_string(&rank).unwrap()),
         _ => Err(Status::NotFound),
@@ -133,8 +275,8 @@ async fn langs(pool: &State<Pool<Postgres>>, from: &str, to: &str) -> Result<Str
 }
 
 #[get("/projects/<from>/<to>")]
-async fn projects(pool: &State<Pool<Postgres>>, from: &str, to: &str) -> Result<String, Status> {
-    let rank = RankingItem::get_projects_ranking(from, to, &pool).await;
+async fn projects(source: &State<Source>, from: &str, to: &str) -> Result<String, Status> {
+    let rank = RankingItem::get_projects_ranking(from, to, source).await;
     match rank {
         Ok(rank) => Ok(serde_json::to_string(&rank).unwrap()),
         _ => Err(Status::NotFound),
@@ -176,14 +318,76 @@ fn index() -> Custom<Vec<u8>> {
     Custom(ContentType::HTML, dat)
 }
 
+/// Seconds between two scans of `ARCHIVE_DIR` when `ARCHIVE_POLL_SECS` is not set.
+const DEFAULT_ARCHIVE_POLL_SECS: u64 = 10;
+
+/// Indexes `dir` and keeps re-reading the files that change in the background.
+///
+/// The directory is polled: every `ARCHIVE_POLL_SECS` the modification time
+/// and size of each file are compared with the last scan. Changed files are
+/// read without holding the lock, which is only taken to swap in the new days.
+fn open_archive(dir: String) -> anyhow::Result<Arc<RwLock<ArchiveIndex>>> {
+    let mut scanner = ArchiveScanner::new(dir);
+    let index = scanner.refresh()?.unwrap_or_default();
+    println!(
+        "archive {}: {} day(s)",
+        scanner.dir().display(),
+        index.len()
+    );
+    let poll = env::var("ARCHIVE_POLL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_ARCHIVE_POLL_SECS);
+    let index = Arc::new(RwLock::new(index));
+    let watched = index.clone();
+    rocket::tokio::spawn(async move {
+        let mut interval = rocket::tokio::time::interval(Duration::from_secs(poll.max(1)));
+        loop {
+            interval.tick().await;
+            // the scanner moves to the blocking thread and comes back with the result
+            let scanned = rocket::tokio::task::spawn_blocking(move || {
+                let res = scanner.refresh();
+                (scanner, res)
+            })
+            .await;
+            let res = match scanned {
+                Ok((returned, res)) => {
+                    scanner = returned;
+                    res
+                }
+                Err(e) => {
+                    println!("archive scanner stopped: {}", e);
+                    break;
+                }
+            };
+            match res {
+                Ok(Some(fresh)) => {
+                    let days = fresh.len();
+                    *watched.write().expect("archive index poisoned") = fresh;
+                    println!("archive reloaded: {} day(s)", days);
+                }
+                Ok(None) => {}
+                Err(e) => println!("archive scan error: {}", e),
+            }
+        }
+    });
+    Ok(index)
+}
+
 #[rocket::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
-    let database_url = env::var("DATABASE_URL")?;
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await?;
+    let source = match env::var("ARCHIVE_DIR") {
+        Ok(dir) => Source::Archive(open_archive(dir)?),
+        Err(_) => {
+            let database_url = env::var("DATABASE_URL")?;
+            let pool = DbPool::connect(&database_url).await?;
+            // get_summary and bulk_regist own the schema, only make sure it matches
+            let version = pool.schema_version().await;
+            comdb::check_schema_version(version)?;
+            Source::Db(pool)
+        }
+    };
     let prefix = match env::var("PREFIX") {
         Ok(pfx) => pfx,
         Err(_) => "".into(),
@@ -197,7 +401,7 @@ async fn main() -> anyhow::Result<()> {
         )
         .mount(format!("/{}", &prefix), routes![statics])
         .register(format!("/{}", &prefix), catchers![index])
-        .manage(pool)
+        .manage(source)
         .configure(Config {
             port: 5005,
             ..Config::default()